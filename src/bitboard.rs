@@ -0,0 +1,473 @@
+//! Bitboard and board geometry.
+//! A single bitboard type wide enough for every supported board size (up to 13x13 = 169 squares),
+//! plus the masks (corners, throne, edges) that depend on the size of the board being played.
+//!
+//! Square indices are row-major: idx = row * size + col.
+
+use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Deref, Not, Shl, Shr};
+
+/// Largest supported board (13x13).
+pub const MAX_BOARD_SIZE: usize = 13;
+pub const MAX_SQUARES: usize = MAX_BOARD_SIZE * MAX_BOARD_SIZE;
+/// Number of 64-bit words needed to store MAX_SQUARES bits.
+const WORDS: usize = MAX_SQUARES.div_ceil(64);
+
+/// Fixed width bitboard: 1 means piece (or masked square) is present, 0 means empty.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Debug)]
+pub struct Bitboard([u64; WORDS]);
+
+impl Bitboard {
+    pub const EMPTY: Bitboard = Bitboard([0; WORDS]);
+
+    /// Bitboard with a single bit set.
+    #[inline(always)]
+    pub fn from_index(idx: usize) -> Self {
+        let mut bb = Self::EMPTY;
+        bb.set(idx);
+        bb
+    }
+
+    #[inline(always)]
+    pub fn get(&self, idx: usize) -> bool {
+        (self.0[idx >> 6] >> (idx & 63)) & 1 == 1
+    }
+
+    #[inline(always)]
+    pub fn set(&mut self, idx: usize) {
+        self.0[idx >> 6] |= 1u64 << (idx & 63);
+    }
+
+    #[inline(always)]
+    pub fn clear(&mut self, idx: usize) {
+        self.0[idx >> 6] &= !(1u64 << (idx & 63));
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|&w| w == 0)
+    }
+
+    /// True if the two bitboards share at least one square.
+    #[inline(always)]
+    pub fn intersects(&self, other: Bitboard) -> bool {
+        !(*self & other).is_empty()
+    }
+
+    #[inline]
+    pub fn count_ones(&self) -> u32 {
+        self.0.iter().map(|w| w.count_ones()).sum()
+    }
+
+    /// Index of the lowest set bit (None if empty).
+    /// Used to locate the king.
+    #[inline]
+    pub fn first(&self) -> Option<usize> {
+        for (i, &w) in self.0.iter().enumerate() {
+            if w != 0 {
+                return Some(i * 64 + w.trailing_zeros() as usize);
+            }
+        }
+        None
+    }
+
+    /// The len bits starting at index offset, as an integer (len <= 32).
+    /// Used to extract a row of the board.
+    #[inline(always)]
+    pub fn bits(&self, offset: usize, len: usize) -> u32 {
+        let (word, bit) = (offset >> 6, offset & 63);
+        let mut x = self.0[word] >> bit;
+        if bit + len > 64 { x |= self.0[word + 1] << (64 - bit); }
+        (x & ((1u64 << len) - 1)) as u32
+    }
+
+    /// Iterate over the indices of the set bits, in increasing order.
+    #[inline]
+    pub fn iter(&self) -> BitIter {
+        BitIter { bb: *self }
+    }
+}
+
+/// Iterator over the set bits of a bitboard.
+pub struct BitIter {
+    bb: Bitboard,
+}
+
+impl Iterator for BitIter {
+    type Item = usize;
+
+    #[inline]
+    fn next(&mut self) -> Option<usize> {
+        for (i, w) in self.bb.0.iter_mut().enumerate() {
+            if *w != 0 {
+                let bit = w.trailing_zeros() as usize;
+                *w &= *w - 1; // Clear lowest bit.
+                return Some(i * 64 + bit);
+            }
+        }
+        None
+    }
+}
+
+// Bitwise operators, applied word by word.
+macro_rules! impl_bit_op {
+    ($trait:ident, $fn:ident, $assign_trait:ident, $assign_fn:ident, $op:tt) => {
+        impl $trait for Bitboard {
+            type Output = Bitboard;
+            #[inline(always)]
+            fn $fn(self, rhs: Bitboard) -> Bitboard {
+                let mut out = self;
+                for i in 0..WORDS { out.0[i] = self.0[i] $op rhs.0[i]; }
+                out
+            }
+        }
+        impl $assign_trait for Bitboard {
+            #[inline(always)]
+            fn $assign_fn(&mut self, rhs: Bitboard) {
+                for i in 0..WORDS { self.0[i] = self.0[i] $op rhs.0[i]; }
+            }
+        }
+    };
+}
+impl_bit_op!(BitAnd, bitand, BitAndAssign, bitand_assign, &);
+impl_bit_op!(BitOr, bitor, BitOrAssign, bitor_assign, |);
+impl_bit_op!(BitXor, bitxor, BitXorAssign, bitxor_assign, ^);
+
+impl Not for Bitboard {
+    type Output = Bitboard;
+    #[inline(always)]
+    fn not(self) -> Bitboard {
+        let mut out = self;
+        for w in out.0.iter_mut() { *w = !*w; }
+        out
+    }
+}
+
+/// Shift towards higher indices. Bits shifted past the last word are lost.
+impl Shl<usize> for Bitboard {
+    type Output = Bitboard;
+    #[inline(always)]
+    fn shl(self, shift: usize) -> Bitboard {
+        let mut out = Bitboard::EMPTY;
+        let (word_shift, bit_shift) = (shift / 64, shift % 64);
+        for i in word_shift..WORDS {
+            let src = i - word_shift;
+            out.0[i] = self.0[src] << bit_shift;
+            if bit_shift > 0 && src > 0 { out.0[i] |= self.0[src - 1] >> (64 - bit_shift); }
+        }
+        out
+    }
+}
+
+/// Shift towards lower indices.
+impl Shr<usize> for Bitboard {
+    type Output = Bitboard;
+    #[inline(always)]
+    fn shr(self, shift: usize) -> Bitboard {
+        let mut out = Bitboard::EMPTY;
+        let (word_shift, bit_shift) = (shift / 64, shift % 64);
+        for i in 0..WORDS.saturating_sub(word_shift) {
+            let src = i + word_shift;
+            out.0[i] = self.0[src] >> bit_shift;
+            if bit_shift > 0 && src + 1 < WORDS { out.0[i] |= self.0[src + 1] << (64 - bit_shift); }
+        }
+        out
+    }
+}
+
+// ========================
+//          LINES
+// ========================
+
+/// A bitboard split into rows and columns, one integer per line:
+/// square (r, c) is bit c of rows[r] and bit r of cols[c].
+/// Sliding moves along a line are then computed with a few shifts and masks (see line_slides).
+#[derive(Clone, Copy, Debug)]
+pub struct Lines {
+    pub rows: [u32; MAX_BOARD_SIZE],
+    pub cols: [u32; MAX_BOARD_SIZE],
+}
+
+/// Positions reachable by sliding from pos along a line of len squares with the given occupancy,
+/// stopping before the first occupied square: (towards lower positions, towards higher positions).
+#[inline(always)]
+pub fn line_slides(occupied: u32, pos: usize, len: usize) -> (u32, u32) {
+    // Sentinel bits just outside the line stop the slides at the edges:
+    // bit len above, and bit 0 of the shifted occupancy (position -1) below.
+    let above = (occupied | 1 << len) & !((2 << pos) - 1);
+    let high = (1 << above.trailing_zeros()) - (2 << pos);
+    let below = ((occupied << 1) | 1) & ((2 << pos) - 1);
+    let low = (1 << pos) - (1 << (31 - below.leading_zeros()));
+    (low, high)
+}
+
+/// Iterator over the positions set in a line mask, in increasing order (or decreasing with rev()).
+pub struct LineIter(pub u32);
+
+impl Iterator for LineIter {
+    type Item = usize;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<usize> {
+        if self.0 == 0 { return None; }
+        let pos = self.0.trailing_zeros() as usize;
+        self.0 &= self.0 - 1; // Clear lowest bit.
+        Some(pos)
+    }
+}
+
+impl DoubleEndedIterator for LineIter {
+    #[inline(always)]
+    fn next_back(&mut self) -> Option<usize> {
+        if self.0 == 0 { return None; }
+        let pos = 31 - self.0.leading_zeros() as usize;
+        self.0 ^= 1 << pos; // Clear highest bit.
+        Some(pos)
+    }
+}
+
+// ========================
+//      BOARD GEOMETRY
+// ========================
+
+/// Indices of the orthogonal neighbors of a square, stored inline (no heap allocation).
+/// Dereferences to a slice.
+#[derive(Clone, Copy, Debug)]
+pub struct Neighbors {
+    squares: [usize; 4],
+    len: usize,
+}
+
+impl Neighbors {
+    #[inline(always)]
+    fn push(&mut self, idx: usize) {
+        self.squares[self.len] = idx;
+        self.len += 1;
+    }
+}
+
+impl Deref for Neighbors {
+    type Target = [usize];
+
+    #[inline(always)]
+    fn deref(&self) -> &[usize] {
+        &self.squares[..self.len]
+    }
+}
+
+impl IntoIterator for Neighbors {
+    type Item = usize;
+    type IntoIter = std::iter::Take<std::array::IntoIter<usize, 4>>;
+
+    #[inline(always)]
+    fn into_iter(self) -> Self::IntoIter {
+        self.squares.into_iter().take(self.len)
+    }
+}
+
+impl<'a> IntoIterator for &'a Neighbors {
+    type Item = &'a usize;
+    type IntoIter = std::slice::Iter<'a, usize>;
+
+    #[inline(always)]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Masks and indices that depend only on the size of the board.
+/// Computed once when a game is created and copied with the state.
+#[derive(Clone, Copy, Debug)]
+pub struct BoardGeometry {
+    pub size: usize,
+    pub squares: usize,
+    /// All the squares of the board.
+    pub board: Bitboard,
+    pub corners: Bitboard,
+    pub throne: Bitboard,
+    pub throne_idx: usize,
+    /// Restricted squares (Corners + Throne).
+    pub restricted: Bitboard,
+    /// Edges.
+    pub row_first: Bitboard,
+    pub row_last: Bitboard,
+    pub col_first: Bitboard,
+    pub col_last: Bitboard,
+    pub edges: Bitboard,
+}
+
+impl BoardGeometry {
+    /// Board sizes must be odd (the throne is the central square) and fit in a Bitboard.
+    pub fn new(size: usize) -> Self {
+        if !(5..=MAX_BOARD_SIZE).contains(&size) || size.is_multiple_of(2) {
+            panic!("Unsupported board size {}: it must be odd and between 5 and {}.", size, MAX_BOARD_SIZE);
+        }
+        let squares = size * size;
+        let last = size - 1;
+
+        let mut board = Bitboard::EMPTY;
+        let mut row_first = Bitboard::EMPTY;
+        let mut row_last = Bitboard::EMPTY;
+        let mut col_first = Bitboard::EMPTY;
+        let mut col_last = Bitboard::EMPTY;
+        for i in 0..squares {
+            board.set(i);
+            let (r, c) = (i / size, i % size);
+            if r == 0 { row_first.set(i); }
+            if r == last { row_last.set(i); }
+            if c == 0 { col_first.set(i); }
+            if c == last { col_last.set(i); }
+        }
+
+        let mut corners = Bitboard::EMPTY;
+        corners.set(0);
+        corners.set(last);
+        corners.set(last * size);
+        corners.set(squares - 1);
+
+        let throne_idx = (size / 2) * size + size / 2;
+        let throne = Bitboard::from_index(throne_idx);
+
+        Self {
+            size,
+            squares,
+            board,
+            corners,
+            throne,
+            throne_idx,
+            restricted: corners | throne,
+            row_first,
+            row_last,
+            col_first,
+            col_last,
+            edges: row_first | row_last | col_first | col_last,
+        }
+    }
+
+    /// The squares of bb plus their orthogonal neighbors (one step of a flood fill).
+    #[inline]
+    pub fn dilate(&self, bb: Bitboard) -> Bitboard {
+        // Horizontal shifts must not wrap around to the next/previous row.
+        let west = (bb >> 1) & !self.col_last;
+        let east = (bb << 1) & !self.col_first;
+        let north = bb >> self.size;
+        let south = bb << self.size;
+        (bb | west | east | north | south) & self.board
+    }
+
+    /// Orthogonal neighbors of idx (up to four): north, south, west, east.
+    #[inline(always)]
+    pub fn neighbors(&self, idx: usize) -> Neighbors {
+        let mut n = Neighbors { squares: [0; 4], len: 0 };
+        let size = self.size;
+        let r = idx / size;
+        let c = idx % size;
+
+        if r > 0 { n.push(idx - size); } // North
+        if r < size - 1 { n.push(idx + size); } // South
+        if c > 0 { n.push(idx - 1); } // West
+        if c < size - 1 { n.push(idx + 1); } // East
+
+        n
+    }
+
+    /// Split a bitboard into rows and columns.
+    #[inline]
+    pub fn lines(&self, bb: Bitboard) -> Lines {
+        let mut lines = Lines { rows: [0; MAX_BOARD_SIZE], cols: [0; MAX_BOARD_SIZE] };
+        for r in 0..self.size {
+            let row = bb.bits(r * self.size, self.size);
+            lines.rows[r] = row;
+            for c in LineIter(row) { lines.cols[c] |= 1 << r; }
+        }
+        lines
+    }
+
+    /// True if idx is one of the four squares orthogonally adjacent to the throne.
+    #[inline(always)]
+    pub fn is_next_to_throne(&self, idx: usize) -> bool {
+        let t = self.throne_idx;
+        idx == t - 1 || idx == t + 1 || idx == t - self.size || idx == t + self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bits_across_words() {
+        let mut bb = Bitboard::EMPTY;
+        for idx in [0, 63, 64, 100, 127, 128, MAX_SQUARES - 1] {
+            bb.set(idx);
+            assert!(bb.get(idx));
+        }
+        assert_eq!(bb.count_ones(), 7);
+        assert_eq!(bb.iter().collect::<Vec<_>>(), vec![0, 63, 64, 100, 127, 128, MAX_SQUARES - 1]);
+        bb.clear(64);
+        assert!(!bb.get(64) && bb.get(63) && bb.get(100));
+        assert_eq!(bb.first(), Some(0));
+    }
+
+    #[test]
+    fn shifts_cross_words() {
+        for idx in [0, 63, 64, 100, 127, 128, MAX_SQUARES - 1] {
+            let bb = Bitboard::from_index(idx);
+            for shift in [1, 13, 63, 64, 65] {
+                let expected = if idx + shift < WORDS * 64 { Bitboard::from_index(idx + shift) } else { Bitboard::EMPTY };
+                assert_eq!(bb << shift, expected, "{} << {}", idx, shift);
+                let expected = if idx >= shift { Bitboard::from_index(idx - shift) } else { Bitboard::EMPTY };
+                assert_eq!(bb >> shift, expected, "{} >> {}", idx, shift);
+            }
+        }
+    }
+
+    #[test]
+    fn slides_stop_before_pieces_and_edges() {
+        // Line of 7 squares: .x.P..x (bits 1 and 6 occupied), sliding from 3.
+        let occupied = 1 << 1 | 1 << 6;
+        assert_eq!(line_slides(occupied, 3, 7), (1 << 2, 1 << 4 | 1 << 5));
+        // Empty line: from one edge to the other.
+        assert_eq!(line_slides(0, 0, 13), (0, (1 << 13) - 2));
+        assert_eq!(line_slides(0, 12, 13), ((1 << 12) - 1, 0));
+        assert_eq!(LineIter(0b10110).collect::<Vec<_>>(), [1, 2, 4]);
+        assert_eq!(LineIter(0b10110).rev().collect::<Vec<_>>(), [4, 2, 1]);
+    }
+
+    #[test]
+    fn lines_split_rows_and_columns() {
+        let geo = BoardGeometry::new(11);
+        let mut bb = Bitboard::EMPTY;
+        bb.set(2 * 11 + 5);
+        bb.set(10 * 11 + 10);
+        let lines = geo.lines(bb);
+        assert_eq!((lines.rows[2], lines.cols[5]), (1 << 5, 1 << 2));
+        assert_eq!((lines.rows[10], lines.cols[10]), (1 << 10, 1 << 10));
+        assert_eq!(lines.rows.iter().filter(|&&row| row != 0).count(), 2);
+    }
+
+    #[test]
+    fn geometry_of_every_size() {
+        for size in [5, 7, 9, 11, 13] {
+            let geo = BoardGeometry::new(size);
+            assert_eq!(geo.board.count_ones() as usize, size * size);
+            assert_eq!(geo.restricted.count_ones(), 5);
+            assert_eq!(geo.throne_idx, size * size / 2);
+            assert!(geo.corners.get(0) && geo.corners.get(size * size - 1));
+            assert_eq!(geo.edges.count_ones() as usize, 4 * (size - 1));
+
+            // A step of the flood fill from the east edge does not wrap around to the next row.
+            let east = Bitboard::from_index(2 * size - 1);
+            let mut expected = east;
+            for n in geo.neighbors(2 * size - 1) { expected.set(n); }
+            assert_eq!(geo.dilate(east), expected);
+            assert_eq!(geo.neighbors(0).len(), 2);
+            assert_eq!(geo.neighbors(geo.throne_idx).len(), 4);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn even_sizes_are_rejected() {
+        BoardGeometry::new(8);
+    }
+}
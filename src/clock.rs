@@ -0,0 +1,234 @@
+//! Game clocks with Fischer increment, and the engine's use of its time.
+//! A time control is written <minutes>+<seconds>, e.g. 5+3: five minutes per side
+//! and three seconds added after each move. A player whose time runs out loses.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use crate::hnefatafl::GameState;
+use crate::mcts::SearchLimits;
+use crate::outcome::Player;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeControl {
+    /// Time of each side at the start of the game.
+    pub base: Duration,
+    /// Added to the clock of a player after each of his moves.
+    pub increment: Duration,
+}
+
+impl TimeControl {
+    pub fn new(base: Duration, increment: Duration) -> Self {
+        Self { base, increment }
+    }
+}
+
+impl FromStr for TimeControl {
+    type Err = String;
+
+    /// Parse <minutes>+<seconds> (e.g. 5+3). Without increment, 5 means 5+0.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (base, increment) = s.trim().split_once('+').unwrap_or((s.trim(), "0"));
+        let base: f64 = base.trim().parse().map_err(|_| format!("invalid base time {}", base))?;
+        let increment: f64 = increment.trim().parse().map_err(|_| format!("invalid increment {}", increment))?;
+        if !(base > 0.0 && increment >= 0.0 && base.is_finite() && increment.is_finite()) {
+            return Err("the base time must be positive and the increment not negative".to_string());
+        }
+        Ok(Self::new(Duration::from_secs_f64(base * 60.0), Duration::from_secs_f64(increment)))
+    }
+}
+
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}+{}", self.base.as_secs_f64() / 60.0, self.increment.as_secs_f64())
+    }
+}
+
+// ========================
+//        GAME CLOCK
+// ========================
+
+/// Remaining time of both sides.
+/// Only the clock of the player to move runs, from start_turn to end_turn.
+#[derive(Clone, Debug)]
+pub struct GameClock {
+    control: TimeControl,
+    /// Black, White.
+    remaining: [Duration; 2],
+    turn_start: Option<Instant>,
+}
+
+impl GameClock {
+    pub fn new(control: TimeControl) -> Self {
+        Self { control, remaining: [control.base; 2], turn_start: None }
+    }
+
+    pub fn increment(&self) -> Duration {
+        self.control.increment
+    }
+
+    /// Time left to the player, not counting the turn in progress.
+    pub fn remaining(&self, player: Player) -> Duration {
+        self.remaining[player as usize]
+    }
+
+    /// Start the clock of the player to move.
+    pub fn start_turn(&mut self) {
+        self.turn_start = Some(Instant::now());
+    }
+
+    /// Stop the clock of the player who just moved and add the increment.
+    /// Returns false if his time ran out before he moved (flag fall): he loses the game.
+    pub fn end_turn(&mut self, player: Player) -> bool {
+        if !self.stop_turn(player) { return false; }
+        self.remaining[player as usize] += self.control.increment;
+        true
+    }
+
+    /// Stop the clock of the player to move and charge him the time he used, without increment
+    /// (he took moves back instead of moving). Returns false if his time ran out.
+    pub fn stop_turn(&mut self, player: Player) -> bool {
+        let elapsed = self.turn_start.take().map_or(Duration::ZERO, |start| start.elapsed());
+        let remaining = &mut self.remaining[player as usize];
+        if elapsed > *remaining {
+            *remaining = Duration::ZERO;
+            return false;
+        }
+        *remaining -= elapsed;
+        true
+    }
+}
+
+/// Time as minutes:seconds.tenths.
+fn write_time(f: &mut fmt::Formatter, time: Duration) -> fmt::Result {
+    let tenths = time.as_millis() / 100;
+    write!(f, "{}:{:02}.{}", tenths / 600, tenths / 10 % 60, tenths % 10)
+}
+
+impl fmt::Display for GameClock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Black ")?;
+        write_time(f, self.remaining(Player::Black))?;
+        write!(f, " | White ")?;
+        write_time(f, self.remaining(Player::White))
+    }
+}
+
+// ========================
+//       TIME MANAGER
+// ========================
+
+/// Fewest and most moves the engine expects to still play (see TimeManager::moves_to_go).
+const MIN_MOVES_TO_GO: u32 = 10;
+const MAX_MOVES_TO_GO: u32 = 40;
+
+/// Decides how long the engine thinks on each move, from the remaining time and the game phase.
+/// The search aims at a target time, and goes on up to a maximum while its best move keeps changing
+/// (see SearchLimits::with_target_time).
+#[derive(Clone, Copy, Debug)]
+pub struct TimeManager {
+    /// Kept aside on every move for the time not spent searching (move generation, output, ...).
+    pub move_overhead: Duration,
+    /// How many times the target time an unstable search may take.
+    pub max_factor: u32,
+}
+
+impl Default for TimeManager {
+    fn default() -> Self {
+        Self { move_overhead: Duration::from_millis(50), max_factor: 3 }
+    }
+}
+
+impl TimeManager {
+    /// Limits of the search of the next move of state.player, whose time is on the clock.
+    pub fn limits(&self, state: &GameState, clock: &GameClock) -> SearchLimits {
+        let budget = clock.remaining(state.player).saturating_sub(self.move_overhead);
+        let target = budget / Self::moves_to_go(state) + clock.increment() * 3 / 4;
+        // Never more than a quarter of the clock on a single move.
+        let max = (target * self.max_factor).min(budget / 4).max(target.min(budget));
+        SearchLimits::time(max).with_target_time(target.min(max))
+    }
+
+    /// Game phase: the engine expects about two more moves per piece on the board,
+    /// so it thinks less in the opening than in a thinned out endgame.
+    fn moves_to_go(state: &GameState) -> u32 {
+        let pieces = (state.black_pieces | state.white_pieces | state.king_piece).count_ones();
+        (2 * pieces).clamp(MIN_MOVES_TO_GO, MAX_MOVES_TO_GO)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::RuleSet;
+    use crate::zobrist::Zobrist;
+
+    #[test]
+    fn parse_time_control() {
+        let control: TimeControl = "5+3".parse().unwrap();
+        assert_eq!(control, TimeControl::new(Duration::from_secs(300), Duration::from_secs(3)));
+        assert_eq!(control.to_string(), "5+3");
+        assert_eq!("0.5".parse(), Ok(TimeControl::new(Duration::from_secs(30), Duration::ZERO)));
+        for invalid in ["", "five", "0+1", "-1+2", "5+-1", "5+x", "inf+0"] {
+            assert!(invalid.parse::<TimeControl>().is_err(), "{} parsed", invalid);
+        }
+    }
+
+    #[test]
+    fn clock_adds_the_increment_and_falls() {
+        // Without start_turn, no time is spent.
+        let mut clock = GameClock::new("1+2".parse().unwrap());
+        assert!(clock.end_turn(Player::Black));
+        assert_eq!(clock.remaining(Player::Black), Duration::from_secs(62));
+        assert_eq!(clock.remaining(Player::White), Duration::from_secs(60));
+        assert_eq!(clock.to_string(), "Black 1:02.0 | White 1:00.0");
+        clock.start_turn();
+        assert!(clock.end_turn(Player::White));
+        assert!(clock.remaining(Player::White) > Duration::from_secs(61));
+
+        let mut clock = GameClock::new(TimeControl::new(Duration::from_millis(1), Duration::from_secs(1)));
+        clock.start_turn();
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(!clock.end_turn(Player::White));
+        assert_eq!(clock.remaining(Player::White), Duration::ZERO);
+    }
+
+    #[test]
+    fn takeback_charges_the_time_used() {
+        let mut clock = GameClock::new("1+2".parse().unwrap());
+        clock.start_turn();
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(clock.stop_turn(Player::White));
+        let remaining = clock.remaining(Player::White);
+        assert!(remaining <= Duration::from_millis(59_980), "{:?}", remaining);
+        assert_eq!(clock.remaining(Player::Black), Duration::from_secs(60));
+
+        // Taking moves back does not stop a flag fall.
+        let mut clock = GameClock::new(TimeControl::new(Duration::from_millis(1), Duration::from_secs(1)));
+        clock.start_turn();
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(!clock.stop_turn(Player::Black));
+        assert_eq!(clock.remaining(Player::Black), Duration::ZERO);
+    }
+
+    #[test]
+    fn time_manager_spreads_the_clock() {
+        let z_table = Zobrist::new(1);
+        let manager = TimeManager::default();
+        let clock = GameClock::new("1+0".parse().unwrap());
+        let budget = Duration::from_secs(60) - manager.move_overhead;
+
+        // 13 pieces on the board: about 26 moves to go, and up to three times the target.
+        let start = GameState::new(RuleSet::default(), &z_table);
+        assert_eq!(manager.limits(&start, &clock), SearchLimits::time(budget / 26 * 3).with_target_time(budget / 26));
+
+        // In the endgame, never more than a quarter of the clock.
+        let endgame = GameState::from_fen("7/1B5/7/3K3/7/5B1/7 w 0", RuleSet::default(), &z_table).unwrap();
+        assert_eq!(manager.limits(&endgame, &clock), SearchLimits::time(budget / 4).with_target_time(budget / 10));
+
+        // Three quarters of the increment are spent on top.
+        let clock = GameClock::new("1+4".parse().unwrap());
+        let target = budget / 26 + Duration::from_secs(3);
+        assert_eq!(manager.limits(&start, &clock), SearchLimits::time(budget / 4).with_target_time(target));
+    }
+}
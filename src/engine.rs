@@ -0,0 +1,214 @@
+//! The engine on a worker thread, driven by commands, for interactive front ends and protocol adapters.
+//! Searches run in the background: the caller keeps control, can stop them at any time,
+//! and receives their progress and results as reports.
+
+use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use crate::hnefatafl::GameState;
+use crate::mcts::{MCTS, SearchLimits, SearchProgress, SimulationType};
+use crate::moves::Move;
+use crate::outcome::GameOutcome;
+
+/// Engine settings that can be changed between searches.
+#[derive(Clone, Copy, Debug)]
+pub enum EngineOption {
+    Simulation(SimulationType),
+    CanonicalHash(bool),
+    SmartStop(bool),
+}
+
+impl EngineOption {
+    fn apply(self, engine: &mut MCTS) {
+        match self {
+            EngineOption::Simulation(sim_type) => engine.sim_type = sim_type,
+            EngineOption::CanonicalHash(on) => engine.canonical_hash = on,
+            EngineOption::SmartStop(on) => engine.smart_stop = on,
+        }
+    }
+}
+
+/// Parse on/off (or true/false).
+fn parse_switch(s: &str) -> Result<bool, String> {
+    match s.trim().to_ascii_lowercase().as_str() {
+        "on" | "true" => Ok(true),
+        "off" | "false" => Ok(false),
+        other => Err(format!("expected on or off, not {}", other)),
+    }
+}
+
+impl FromStr for EngineOption {
+    type Err = String;
+
+    /// Parse <name> <value>: simulation light|heavy|parallel-light <n>|parallel-heavy <n>,
+    /// canonical_hash on|off or smart_stop on|off.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s.trim().split_once(' ').ok_or("expected an option and its value")?;
+        match name {
+            "simulation" => {
+                let (kind, batch) = value.trim().split_once(' ').unwrap_or((value.trim(), ""));
+                let batch = || batch.trim().parse::<usize>().map_err(|_| format!("invalid batch size {}", batch));
+                let sim_type = match kind {
+                    "light" => SimulationType::Light,
+                    "heavy" => SimulationType::Heavy,
+                    "parallel-light" => SimulationType::ParallelLight(batch()?),
+                    "parallel-heavy" => SimulationType::ParallelHeavy(batch()?),
+                    other => return Err(format!("unknown simulation {}", other)),
+                };
+                Ok(EngineOption::Simulation(sim_type))
+            }
+            "canonical_hash" => Ok(EngineOption::CanonicalHash(parse_switch(value)?)),
+            "smart_stop" => Ok(EngineOption::SmartStop(parse_switch(value)?)),
+            other => Err(format!("unknown option {}", other)),
+        }
+    }
+}
+
+/// What the front end asks of the engine (see EngineHandle::send).
+#[derive(Clone)]
+pub enum Command {
+    /// Search the current position within the limits, then report the best move.
+    Go(SearchLimits),
+    /// Interrupt the search in progress: it reports the best move found so far.
+    Stop,
+    /// Position searched by the next Go.
+    Position(Box<GameState>),
+    SetOption(EngineOption),
+    Quit,
+}
+
+/// What the engine tells the front end.
+#[derive(Clone, Debug)]
+pub enum Report {
+    /// Sent regularly during a search.
+    Progress(SearchProgress),
+    BestMove(Move),
+    /// Answer to Go when the game is already over.
+    GameOver(GameOutcome),
+}
+
+/// Handle to an engine running on its own thread.
+/// Commands are queued and run in order; Stop is the exception and acts at once.
+/// Dropping the handle stops the search in progress and ends the thread.
+pub struct EngineHandle {
+    commands: Sender<Command>,
+    /// Shared with the engine (see MCTS::stop_flag).
+    stop: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl EngineHandle {
+    /// Start the engine on a worker thread, on the given position.
+    /// Returns the handle, and the receiving end of the reports.
+    pub fn spawn(mut engine: MCTS, position: GameState) -> (Self, Receiver<Report>) {
+        let (commands, inbox) = mpsc::channel();
+        let (reports, outbox) = mpsc::channel();
+        let stop = engine.stop_flag();
+        let progress = reports.clone();
+        engine.on_progress = Some(Box::new(move |p| { progress.send(Report::Progress(p)).ok(); }));
+        let worker = thread::spawn(move || run(engine, position, inbox, reports));
+        (Self { commands, stop, worker: Some(worker) }, outbox)
+    }
+
+    pub fn send(&self, command: Command) {
+        match command {
+            // The worker is busy with the search: tell it directly.
+            Command::Stop => self.stop.store(true, Ordering::Relaxed),
+            command => {
+                // Clear the flag here, not on the worker, so that a Stop sent right after is not lost.
+                if let Command::Go(_) = command {
+                    self.stop.store(false, Ordering::Relaxed);
+                }
+                self.commands.send(command).ok();
+            }
+        }
+    }
+}
+
+impl Drop for EngineHandle {
+    fn drop(&mut self) {
+        self.send(Command::Stop);
+        self.send(Command::Quit);
+        if let Some(worker) = self.worker.take() {
+            worker.join().ok();
+        }
+    }
+}
+
+/// Worker loop: run the commands until Quit, or until the handle is gone.
+fn run(mut engine: MCTS, mut position: GameState, commands: Receiver<Command>, reports: Sender<Report>) {
+    for command in commands {
+        match command {
+            Command::Go(limits) => {
+                let report = match position.check_game_over(&engine.z_table) {
+                    Some(outcome) => Report::GameOver(outcome),
+                    None => Report::BestMove(engine.get_move(&position, &limits, &mut io::sink())),
+                };
+                if reports.send(report).is_err() { break; }
+            }
+            Command::Stop => {}
+            Command::Position(state) => position = *state,
+            Command::SetOption(option) => option.apply(&mut engine),
+            Command::Quit => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::moves::MoveList;
+    use crate::outcome::{EndReason, Player};
+    use crate::rules::RuleSet;
+
+    #[test]
+    fn parse_options() {
+        assert!(matches!("simulation heavy".parse(), Ok(EngineOption::Simulation(SimulationType::Heavy))));
+        assert!(matches!("simulation parallel-light 8".parse(), Ok(EngineOption::Simulation(SimulationType::ParallelLight(8)))));
+        assert!(matches!(" canonical_hash on ".parse(), Ok(EngineOption::CanonicalHash(true))));
+        assert!(matches!("smart_stop FALSE".parse(), Ok(EngineOption::SmartStop(false))));
+        for invalid in ["", "simulation", "simulation fast", "simulation parallel-heavy", "smart_stop maybe", "ponder on"] {
+            assert!(invalid.parse::<EngineOption>().is_err(), "{} parsed", invalid);
+        }
+    }
+
+    #[test]
+    fn engine_on_a_worker_thread() {
+        let engine = MCTS::new(1, SimulationType::Light);
+        let start = GameState::new(RuleSet::default(), &engine.z_table);
+        let escaped = GameState::from_fen("K6/7/7/7/7/7/3B3 b 0", RuleSet::default(), &engine.z_table).unwrap();
+        let mut legal = MoveList::new();
+        start.get_legal_moves(&mut legal, true, &engine.z_table);
+        let (handle, reports) = EngineHandle::spawn(engine, start.clone());
+        let best_move = || loop {
+            match reports.recv_timeout(Duration::from_secs(60)).expect("no answer from the engine") {
+                Report::BestMove(m) => break m,
+                Report::Progress(_) => {}
+                Report::GameOver(outcome) => panic!("game over: {}", outcome),
+            }
+        };
+
+        handle.send(Command::SetOption("smart_stop off".parse().unwrap()));
+        handle.send(Command::Go(SearchLimits::playouts(200)));
+        assert!(legal.contains(&best_move()));
+
+        // An unlimited search answers once stopped.
+        handle.send(Command::Go(SearchLimits::unlimited()));
+        thread::sleep(Duration::from_millis(100));
+        handle.send(Command::Stop);
+        assert!(legal.contains(&best_move()));
+
+        handle.send(Command::Position(Box::new(escaped)));
+        handle.send(Command::Go(SearchLimits::playouts(200)));
+        match reports.recv_timeout(Duration::from_secs(60)) {
+            Ok(Report::GameOver(outcome)) => assert_eq!(outcome, GameOutcome::win(Player::White, EndReason::KingEscaped)),
+            other => panic!("expected the end of the game, got {:?}", other),
+        }
+        drop(handle);
+        assert!(reports.recv().is_err(), "the worker outlived its handle");
+    }
+}
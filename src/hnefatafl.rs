@@ -1,144 +1,571 @@
 //! Game State and rules implementation.
-//! Game: Hnefatafl on 7x7 board with simplified Copenhagen rules:
-//! - No shieldwall rule (4b), no exit forts (6b), no surrounding (7b).
+//! Game: Hnefatafl on 7x7, 9x9, 11x11 or 13x13 boards.
+//! Brandubh, Ard Ri, Tablut and Copenhagen are available as presets (see GameState::from_preset).
+//! The rules are configured with a RuleSet (see rules.rs). The default are simplified Copenhagen rules:
+//! - No shieldwall rule (4b), no exit forts (6b), no surrounding (7b) unless enabled in the RuleSet.
 //! - If the king is not at or next to the throne, he can be captured like any other piece,
 //!   with two enemies on the sides.
 //! - If the king is on the throne, he has to be surrounded on all four sides.
 //! - If the king is next to the throne, he has to be surrounded on the remaining three sides.
+//! - The king wins by reaching a corner (or any edge square with Escape::Edges).
 //! - The corner fields are hostile to all, including the King.
 //! - The throne is always hostile to black and hostile to white if not occupied.
+//! - The king is armed.
 //! - The repetition of a game state results in a loss for white (King side).
+//!   Draws, threefold repetition and counting only positions with the same side to move are configurable.
 //!
-//! The moves are encoded as an array: coords = [start_row, start_col, end_row, end_col]
+//! The moves are Move values, written in tafl notation (see moves.rs).
 
 use std::io::{self, Write};
+use crate::bitboard::{line_slides, Bitboard, BoardGeometry, LineIter, Lines, Neighbors, MAX_BOARD_SIZE, MAX_SQUARES};
+use crate::moves::{Move, MoveList};
+use crate::outcome::{Capture, EndReason, GameOutcome, MoveOutcome, Piece, Player};
+use crate::rules::{Escape, KingCapture, Preset, RepetitionRule, RuleSet};
+use crate::symmetry::Symmetry;
 use crate::zobrist::Zobrist;
 
-/// The maximum number of plies for a game.
-/// Used to implement Rule 8 (Perpetual repetitions result in a loss for white).
-const MAX_GAME_LENGTH: usize = 512;
-
-/// BITBOARD constants.
-const BOARD_SIZE: usize = 7;
-const TOTAL_SQUARES: usize = BOARD_SIZE * BOARD_SIZE;
-/// Masks (for checking the board positions).
-/// Corners:
-const CORNERS: u64 = (1 << 0) | (1 << 6) | (1 << 42) | (1 << 48);
-/// Throne:
-const THRONE: u64 = 1 << 24;
-/// Restricted squares (Corners + Throne)
-const RESTRICTED: u64 = CORNERS | THRONE;
-/// Edges:
-const ROW_0_MASK: u64 = 0x7F;
-const ROW_6_MASK: u64 = 0x7F << 42;
-const COL_0_MASK: u64 = (1<<0)|(1<<7)|(1<<14)|(1<<21)|(1<<28)|(1<<35)|(1<<42);
-const COL_6_MASK: u64 = COL_0_MASK << 6;
-
-/// Board representation used in history.
-/// (black_mask, white_mask, king_mask)
-type BoardSnaphot = (u64, u64, u64, usize);
-
-#[derive(Clone, Copy)]
+/// Starting positions, one string per row.
+/// B = black, W = white pawn, K = king, . = empty.
+const LAYOUT_7X7: [&str; 7] = [
+    "...B...",
+    "...B...",
+    "...W...",
+    "BBWKWBB",
+    "...W...",
+    "...B...",
+    "...B...",
+];
+/// Ard Ri: 7x7 with 16 attackers and 8 defenders.
+const LAYOUT_ARD_RI: [&str; 7] = [
+    "..BBB..",
+    "...B...",
+    "B.WWW.B",
+    "BBWKWBB",
+    "B.WWW.B",
+    "...B...",
+    "..BBB..",
+];
+const LAYOUT_9X9: [&str; 9] = [
+    "...BBB...",
+    "....B....",
+    "....W....",
+    "B...W...B",
+    "BBWWKWWBB",
+    "B...W...B",
+    "....W....",
+    "....B....",
+    "...BBB...",
+];
+const LAYOUT_11X11: [&str; 11] = [
+    "...BBBBB...",
+    ".....B.....",
+    "...........",
+    "B....W....B",
+    "B...WWW...B",
+    "BB.WWKWW.BB",
+    "B...WWW...B",
+    "B....W....B",
+    "...........",
+    ".....B.....",
+    "...BBBBB...",
+];
+const LAYOUT_13X13: [&str; 13] = [
+    "....BBBBB....",
+    "......B......",
+    ".............",
+    ".............",
+    "B.....W.....B",
+    "B....WWW....B",
+    "BB..WWKWW..BB",
+    "B....WWW....B",
+    "B.....W.....B",
+    ".............",
+    ".............",
+    "......B......",
+    "....BBBBB....",
+];
+
+/// A position of the game history, used to implement Rule 8 (perpetual repetitions).
+#[derive(Clone, Copy, Debug)]
+struct HistoryEntry {
+    /// Zobrist hash of the boards, without the side to move.
+    key: u64,
+    /// The boards themselves, to rule out hash collisions.
+    black: Bitboard,
+    white: Bitboard,
+    king: Bitboard,
+    /// Side to move in this position.
+    player: Player,
+    /// How many times the position occurred so far, this one included.
+    occurrence: u32,
+    /// Ply of the first occurrence.
+    first_ply: u32,
+    /// Previous entry with the same key (index + 1, 0 if there is none).
+    prev_same: u32,
+}
+
+impl HistoryEntry {
+    /// Same position for the repetition rule: same boards, and same side to move if side_to_move is set.
+    #[inline(always)]
+    fn same_position(&self, (black, white, king): (Bitboard, Bitboard, Bitboard), player: Player, side_to_move: bool) -> bool {
+        self.black == black && self.white == white && self.king == king && (!side_to_move || self.player == player)
+    }
+}
+
+/// Initial capacity of the history (in plies). It grows as needed: games have no length limit.
+const HISTORY_CAPACITY: usize = 256;
+
+/// The positions of the game in order, the current one last, indexed by key.
+/// Captures cannot be undone, so only the window since the last capture can be repeated;
+/// the positions before it are kept to take the captures back.
+/// The index is an open addressing table from each key to its latest entry (index + 1, 0 for an empty slot),
+/// kept at most half full. Entries are only pushed and popped at the end, so a pop simply hands the slot
+/// back to the previous entry with the same key, or empties it.
+#[derive(Clone, Debug)]
+struct History {
+    entries: Vec<HistoryEntry>,
+    slots: Vec<u32>,
+    /// First entry since the last capture.
+    window_start: usize,
+}
+
+impl History {
+    fn new(first: HistoryEntry) -> Self {
+        let mut history = Self {
+            entries: Vec::with_capacity(HISTORY_CAPACITY),
+            slots: vec![0; 2 * HISTORY_CAPACITY],
+            window_start: 0,
+        };
+        history.push(first);
+        history
+    }
+
+    /// Slot of the key in the index: the one holding its latest entry, or the empty one it would take.
+    #[inline(always)]
+    fn slot(&self, key: u64) -> usize {
+        let mask = self.slots.len() - 1;
+        let mut s = key as usize & mask;
+        while self.slots[s] != 0 && self.entries[self.slots[s] as usize - 1].key != key {
+            s = (s + 1) & mask;
+        }
+        s
+    }
+
+    /// Latest entry of the window with the same position.
+    #[inline]
+    fn latest(&self, key: u64, boards: (Bitboard, Bitboard, Bitboard), player: Player, side_to_move: bool) -> Option<&HistoryEntry> {
+        let mut i = self.slots[self.slot(key)] as usize;
+        while i > self.window_start {
+            let entry = &self.entries[i - 1];
+            if entry.same_position(boards, player, side_to_move) { return Some(entry); }
+            i = entry.prev_same as usize;
+        }
+        None
+    }
+
+    #[inline]
+    fn push(&mut self, mut entry: HistoryEntry) {
+        if 2 * (self.entries.len() + 1) > self.slots.len() {
+            self.reindex(2 * self.slots.len());
+        }
+        let s = self.slot(entry.key);
+        entry.prev_same = self.slots[s];
+        self.entries.push(entry);
+        self.slots[s] = self.entries.len() as u32;
+    }
+
+    #[inline]
+    fn pop(&mut self) {
+        let s = self.slot(self.entries.last().expect("empty history").key);
+        self.slots[s] = self.entries.pop().unwrap().prev_same;
+    }
+
+    /// Rebuild the index with the given number of slots (a power of two), adding the entries in order.
+    fn reindex(&mut self, slots: usize) {
+        self.slots.clear();
+        self.slots.resize(slots, 0);
+        for i in 0..self.entries.len() {
+            let s = self.slot(self.entries[i].key);
+            self.entries[i].prev_same = self.slots[s];
+            self.slots[s] = i as u32 + 1;
+        }
+    }
+
+    /// Copy the window of other, reusing the buffers of self.
+    fn clone_window_from(&mut self, other: &History) {
+        self.entries.clear();
+        self.entries.extend_from_slice(&other.entries[other.window_start..]);
+        self.window_start = 0;
+        let slots = self.slots.len().max((2 * self.entries.len()).next_power_of_two());
+        self.reindex(slots);
+    }
+}
+
+/// Compact record of a move, enough to take it back (see make_move / unmake_move).
+#[derive(Clone, Copy, Debug)]
+pub struct Undo {
+    src: u8,
+    dst: u8,
+    /// Piece type of the mover (0:B, 1:W, 2:K).
+    mover: u8,
+    /// Captured pawns (white if black moved, black otherwise).
+    captured: Bitboard,
+    /// Square of the king, if he was captured.
+    captured_king: Option<u8>,
+    prev_hash: u64,
+    prev_repetition: bool,
+    prev_repetition_dist: Option<usize>,
+    prev_window_start: u32,
+}
+
+impl Undo {
+    /// True if the move captured a piece: it closed the history window.
+    #[inline(always)]
+    fn is_capture(&self) -> bool {
+        !self.captured.is_empty() || self.captured_king.is_some()
+    }
+}
+
+#[derive(Clone)]
 pub struct GameState {
     /// Bitboards: 1 means piece is present, 0 means empty.
-    pub black_pieces: u64,
-    pub white_pieces: u64, // only white pawns
-    pub king_piece: u64, // only the king
+    pub black_pieces: Bitboard,
+    pub white_pieces: Bitboard, // only white pawns
+    pub king_piece: Bitboard, // only the king
 
-    pub player: char,
+    /// Board size and the masks derived from it.
+    pub geo: BoardGeometry,
+    pub rules: RuleSet,
+
+    pub player: Player,
     pub hash: u64,
 
     pub ply_count: usize,
 
     // History for Rule 8 (perpetual repetitions).
-    history: [BoardSnaphot; MAX_GAME_LENGTH],
-    history_len: usize,
+    history: History,
     pub repetition: bool,
     pub repetition_dist: Option<usize>,
 }
 
 impl GameState {
-    pub fn new(z_table: &Zobrist) -> Self {
-        // Initial setup:
-        // let initial_board = [
-        //     ['.', '.', '.', 'B', '.', '.', '.'],
-        //     ['.', '.', '.', 'B', '.', '.', '.'],
-        //     ['.', '.', '.', 'W', '.', '.', '.'],
-        //     ['B', 'B', 'W', 'K', 'W', 'B', 'B'],
-        //     ['.', '.', '.', 'W', '.', '.', '.'],
-        //     ['.', '.', '.', 'B', '.', '.', '.'],
-        //     ['.', '.', '.', 'B', '.', '.', '.'],
-        // ];
-        
-        // Bitboard.
-        let mut black = 0u64;
-        let mut white = 0u64;
-        let mut king = 0u64;
+    /// Standard 7x7 game.
+    pub fn new(rules: RuleSet, z_table: &Zobrist) -> Self {
+        Self::new_sized(7, rules, z_table)
+    }
 
-        let set = |b: &mut u64, r, c| *b |= 1u64 << (r * 7 + c);
+    /// Starting position for the given board size (7, 9, 11 or 13).
+    pub fn new_sized(size: usize, rules: RuleSet, z_table: &Zobrist) -> Self {
+        match size {
+            7 => Self::from_layout(&LAYOUT_7X7, rules, z_table),
+            9 => Self::from_layout(&LAYOUT_9X9, rules, z_table),
+            11 => Self::from_layout(&LAYOUT_11X11, rules, z_table),
+            13 => Self::from_layout(&LAYOUT_13X13, rules, z_table),
+            _ => panic!("No starting position for a {}x{} board.", size, size),
+        }
+    }
 
-        set(&mut black, 0, 3); set(&mut black, 1, 3);
-        set(&mut black, 3, 0); set(&mut black, 3, 1); set(&mut black, 3, 5); set(&mut black, 3, 6);
-        set(&mut black, 5, 3); set(&mut black, 6, 3);
+    /// Starting position and rules of a named variant.
+    pub fn from_preset(preset: Preset, z_table: &Zobrist) -> Self {
+        let rules = preset.rules();
+        match preset {
+            Preset::Brandubh => Self::from_layout(&LAYOUT_7X7, rules, z_table),
+            Preset::ArdRi => Self::from_layout(&LAYOUT_ARD_RI, rules, z_table),
+            Preset::Tablut => Self::from_layout(&LAYOUT_9X9, rules, z_table),
+            Preset::Copenhagen => Self::from_layout(&LAYOUT_11X11, rules, z_table),
+        }
+    }
+
+    /// Build the initial state from a layout (see LAYOUT_7X7).
+    /// Black moves first.
+    fn from_layout(layout: &[&str], rules: RuleSet, z_table: &Zobrist) -> Self {
+        let n = layout.len();
+
+        // Bitboard.
+        let mut black = Bitboard::EMPTY;
+        let mut white = Bitboard::EMPTY;
+        let mut king = Bitboard::EMPTY;
+
+        for (r, row) in layout.iter().enumerate() {
+            for (c, square) in row.chars().enumerate() {
+                match square {
+                    'B' => black.set(r * n + c),
+                    'W' => white.set(r * n + c),
+                    'K' => king.set(r * n + c),
+                    _ => {}
+                }
+            }
+        }
 
-        set(&mut white, 2, 3); 
-        set(&mut white, 3, 2); set(&mut white, 3, 4);
-        set(&mut white, 4, 3);
+        Self::from_boards(n, (black, white, king), Player::Black, 0, rules, z_table)
+    }
 
-        set(&mut king, 3, 3);
+    /// Build a state from the bitboards (black, white, king).
+    /// The history starts with this position.
+    fn from_boards(size: usize, pieces: (Bitboard, Bitboard, Bitboard), player: Player, ply_count: usize, rules: RuleSet, z_table: &Zobrist) -> Self {
+        let mut geo = BoardGeometry::new(size);
+        // With edge escape the corners are ordinary squares.
+        if rules.escape == Escape::Edges { geo.restricted = geo.throne; }
+        let (black, white, king) = pieces;
 
         // Hash.
-        let mut hash = 0u64;
-        for i in 0..49 {
-            let r = i / 7;
-            let c = i % 7;
-            if (black >> i) & 1 == 1 { hash ^= z_table.table[r][c][0]; }
-            if (white >> i) & 1 == 1 { hash ^= z_table.table[r][c][1]; }
-            if (king >> i) & 1 == 1  { hash ^= z_table.table[r][c][2]; }
-        }
-        hash ^= z_table.black_to_move;
+        let key = Self::hash_boards(geo.size, pieces, Symmetry::Identity, z_table);
+        let hash = if player == Player::Black { key ^ z_table.black_to_move } else { key };
 
         // History.
-        let initial_snapshot = (black, white, king, 0);
-        let mut history = [(0,0,0,0); MAX_GAME_LENGTH];
-        history[0] = initial_snapshot;
+        let history = History::new(HistoryEntry {
+            key, black, white, king, player, occurrence: 1, first_ply: ply_count as u32, prev_same: 0,
+        });
 
         Self {
             black_pieces: black,
             white_pieces: white,
             king_piece: king,
-            player: 'B',
+            geo,
+            rules,
+            player,
             hash,
-            ply_count: 0,
+            ply_count,
             history,
-            history_len: 1,
             repetition: false,
             repetition_dist: None,
         }
     }
 
+    /// Zobrist hash of the boards (black, white, king) after applying the symmetry, without the side to move.
+    fn hash_boards(size: usize, pieces: (Bitboard, Bitboard, Bitboard), sym: Symmetry, z_table: &Zobrist) -> u64 {
+        let (black, white, king) = pieces;
+        let mut hash = 0u64;
+        for (p_idx, board) in [black, white, king].into_iter().enumerate() {
+            for i in board.iter() {
+                let (r, c) = sym.apply(i / size, i % size, size);
+                hash ^= z_table.table[r][c][p_idx];
+            }
+        }
+        hash
+    }
+
+    /// Copy other into self, reusing the buffers of self (e.g. for the states of parallel playouts).
+    /// Only the history since the last capture is copied: the moves made before cannot be taken back on self.
+    pub fn clone_window_from(&mut self, other: &GameState) {
+        self.black_pieces = other.black_pieces;
+        self.white_pieces = other.white_pieces;
+        self.king_piece = other.king_piece;
+        self.geo = other.geo;
+        self.rules = other.rules;
+        self.player = other.player;
+        self.hash = other.hash;
+        self.ply_count = other.ply_count;
+        self.history.clone_window_from(&other.history);
+        self.repetition = other.repetition;
+        self.repetition_dist = other.repetition_dist;
+    }
+
+    // ===========================
+    //      POSITION NOTATION
+    // ===========================
+
+    /// Parse a position in FEN-like notation: rows from the top separated by '/',
+    /// B = black, W = white pawn, K = king, a number for a run of empty squares,
+    /// then the side to move (b or w) and optionally the ply count.
+    /// E.g. the 7x7 start: 3B3/3B3/3W3/BBWKWBB/3W3/3B3/3B3 b 0
+    pub fn from_fen(fen: &str, rules: RuleSet, z_table: &Zobrist) -> Result<Self, String> {
+        let mut fields = fen.split_whitespace();
+        let board = fields.next().ok_or("empty position")?;
+        let player = match fields.next() {
+            Some("b") => Player::Black,
+            Some("w") => Player::White,
+            _ => return Err("side to move must be b or w".to_string()),
+        };
+        let ply_count = match fields.next() {
+            Some(ply) => ply.parse().map_err(|_| format!("invalid ply count {}", ply))?,
+            None => 0,
+        };
+        if fields.next().is_some() { return Err("too many fields".to_string()); }
+
+        let rows: Vec<&str> = board.split('/').collect();
+        let n = rows.len();
+        Self::check_parsed_size(n)?;
+
+        let mut black = Bitboard::EMPTY;
+        let mut white = Bitboard::EMPTY;
+        let mut king = Bitboard::EMPTY;
+        for (r, row) in rows.iter().enumerate() {
+            let mut c = 0;
+            let mut empty = 0;
+            for square in row.chars() {
+                if let Some(d) = square.to_digit(10) {
+                    empty = empty * 10 + d as usize;
+                    // Checked on every digit, so that a long run cannot overflow.
+                    if c + empty > n { return Err(format!("row {} is too long", r + 1)); }
+                    continue;
+                }
+                c += empty;
+                empty = 0;
+                if c >= n { return Err(format!("row {} is too long", r + 1)); }
+                match square {
+                    'B' => black.set(r * n + c),
+                    'W' => white.set(r * n + c),
+                    'K' => king.set(r * n + c),
+                    _ => return Err(format!("invalid piece {}", square)),
+                }
+                c += 1;
+            }
+            c += empty;
+            if c != n { return Err(format!("row {} has {} squares instead of {}", r + 1, c, n)); }
+        }
+        Self::from_parsed_boards(n, (black, white, king), player, ply_count, rules, z_table)
+    }
+
+    /// Parse a board diagram (as printed by display) from a text such as a game log:
+    /// the board at the given index (0 for the first one) among those found in the text.
+    /// The side to move is the given player, or else read from the first line after the board that is not blank
+    /// (see parse_side_to_move). Other lines, such as the result at the end of a log, are not read as a side.
+    /// The file and rank labels are ignored, so older logs (numbered from 0) are read as well.
+    pub fn from_diagram(diagram: &str, index: usize, player: Option<Player>, rules: RuleSet, z_table: &Zobrist) -> Result<Self, String> {
+        let mut rows: Vec<Vec<char>> = Vec::new();
+        let mut boards = 0;
+        let mut next_line = None;
+        let mut lines = diagram.lines();
+        while let Some(line) = lines.next() {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+
+            // Board row: rank label followed by the squares.
+            if let Some((&first, squares)) = tokens.split_first()
+            && first.parse::<usize>().is_ok()
+            && !squares.is_empty()
+            && squares.iter().all(|t| matches!(*t, "B" | "W" | "K" | ".")) {
+                rows.push(squares.iter().map(|t| t.chars().next().unwrap()).collect());
+                continue;
+            }
+
+            // Any other line ends the board.
+            if !rows.is_empty() {
+                if boards == index {
+                    next_line = std::iter::once(line).chain(lines).find(|l| !l.trim().is_empty());
+                    break;
+                }
+                boards += 1;
+                rows.clear();
+            }
+        }
+        // Past the end of the text, rows holds the last board, whatever its index.
+        if rows.is_empty() || boards != index { return Err(format!("board {} not found", index)); }
+
+        // Side to move: given, or read from the line after the board.
+        let player = player.or_else(|| Self::parse_side_to_move(next_line?));
+
+        let n = rows.len();
+        let player = player.ok_or("missing side to move after the board: give it explicitly")?;
+        Self::check_parsed_size(n)?;
+
+        let mut black = Bitboard::EMPTY;
+        let mut white = Bitboard::EMPTY;
+        let mut king = Bitboard::EMPTY;
+        for (r, row) in rows.iter().enumerate() {
+            if row.len() != n { return Err(format!("row {} has {} squares instead of {}", r + 1, row.len(), n)); }
+            for (c, &square) in row.iter().enumerate() {
+                match square {
+                    'B' => black.set(r * n + c),
+                    'W' => white.set(r * n + c),
+                    'K' => king.set(r * n + c),
+                    _ => {}
+                }
+            }
+        }
+
+        Self::from_parsed_boards(n, (black, white, king), player, 0, rules, z_table)
+    }
+
+    /// Side to move from a line of a game log: "White to move" (as written by display), the
+    /// "Current Player: White" / "Player White is thinking..." lines of the games, or the side alone (b, w, black or white).
+    /// Any other line, e.g. the result of the game, gives none.
+    fn parse_side_to_move(line: &str) -> Option<Player> {
+        let tokens: Vec<String> = line.split_whitespace().map(str::to_ascii_lowercase).collect();
+        let tokens: Vec<&str> = tokens.iter().map(String::as_str).collect();
+        let side = match tokens.as_slice() {
+            [side] | [side, "to", "move"] | ["current", "player:", side] | ["player", side, "is", "thinking..."] => *side,
+            _ => return None,
+        };
+        match side {
+            "b" | "black" => Some(Player::Black),
+            "w" | "white" => Some(Player::White),
+            _ => None,
+        }
+    }
+
+    /// Board sizes accepted by the parsers (see BoardGeometry::new).
+    fn check_parsed_size(n: usize) -> Result<(), String> {
+        if !(5..=MAX_BOARD_SIZE).contains(&n) || n.is_multiple_of(2) {
+            return Err(format!("unsupported board size {}", n));
+        }
+        Ok(())
+    }
+
+    /// Checks shared by the parsers, then build the state.
+    fn from_parsed_boards(size: usize, pieces: (Bitboard, Bitboard, Bitboard), player: Player, ply_count: usize, rules: RuleSet, z_table: &Zobrist) -> Result<Self, String> {
+        let (black, white, king) = pieces;
+        if king.count_ones() != 1 { return Err("there must be exactly one king".to_string()); }
+
+        let state = Self::from_boards(size, pieces, player, ply_count, rules, z_table);
+        if (black | white).intersects(state.geo.restricted) {
+            return Err("only the king can stand on a restricted square".to_string());
+        }
+        Ok(state)
+    }
+
+    /// Position in FEN-like notation (see from_fen).
+    pub fn to_fen(&self) -> String {
+        let n = self.geo.size;
+        let mut fen = String::new();
+        for r in 0..n {
+            if r > 0 { fen.push('/'); }
+            let mut empty = 0;
+            for c in 0..n {
+                let i = self.idx(r, c);
+                let square = if self.black_pieces.get(i) { 'B' }
+                             else if self.white_pieces.get(i) { 'W' }
+                             else if self.king_piece.get(i) { 'K' }
+                             else { empty += 1; continue; };
+                if empty > 0 { fen += &empty.to_string(); empty = 0; }
+                fen.push(square);
+            }
+            if empty > 0 { fen += &empty.to_string(); }
+        }
+        let side = if self.player == Player::Black { 'b' } else { 'w' };
+        fen += &format!(" {} {}", side, self.ply_count);
+        fen
+    }
+
     /// Display game board in ASCII art.
     // Inside your impl GameState
     pub fn display<W: Write>(&self, writer: &mut W) -> io::Result<()> {
-        writeln!(writer, "  0 1 2 3 4 5 6")?;
-        for r in 0..7 {
-            write!(writer, "{}", r)?;
-            for c in 0..7 {
-                let mask = 1u64 << (r * 7 + c);
-                if (self.black_pieces & mask) != 0 { write!(writer, " B")?; }
-                else if (self.white_pieces & mask) != 0 { write!(writer, " W")?; }
-                else if (self.king_piece & mask) != 0 { write!(writer, " K")?; }
-                else { write!(writer, " .")?; }
+        let n = self.geo.size;
+        // Files (a, b, ...) and ranks (1, 2, ... from the bottom) as in the move notation.
+        // Two characters per column once ranks reach 10.
+        let w = if n > 9 { 2 } else { 1 };
+        write!(writer, "{:>w$}", "")?;
+        for c in 0..n { write!(writer, " {:>w$}", (b'a' + c as u8) as char)?; }
+        writeln!(writer)?;
+        for r in 0..n {
+            write!(writer, "{:>w$}", n - r)?;
+            for c in 0..n {
+                let i = self.idx(r, c);
+                if self.black_pieces.get(i) { write!(writer, " {:>w$}", "B")?; }
+                else if self.white_pieces.get(i) { write!(writer, " {:>w$}", "W")?; }
+                else if self.king_piece.get(i) { write!(writer, " {:>w$}", "K")?; }
+                else { write!(writer, " {:>w$}", ".")?; }
             }
             writeln!(writer)?;
         }
+        writeln!(writer, "{} to move", self.player)?;
         Ok(())
     }
 
     /// Helpeer to get bit index.
     #[inline(always)]
-    fn idx(r: usize, c: usize) -> usize {
-        r * 7 + c
+    fn idx(&self, r: usize, c: usize) -> usize {
+        r * self.geo.size + c
     }
 
     // ===================
@@ -148,18 +575,18 @@ impl GameState {
     /// Compute the hash of a move without applying it.
     /// Used by MCTS for lookups in transpositions table.
     #[inline]
-    pub fn next_hash(&self, coords: &[usize; 4], z_table: &Zobrist) -> u64 {
-        let (sr, sc, er, ec) = (coords[0], coords[1], coords[2], coords[3]);
-        let src_mask = 1 << Self::idx(sr, sc);
-        let dst_mask = 1 << Self::idx(er, ec);
-        let move_mask = src_mask | dst_mask;
+    pub fn next_hash(&self, m: &Move, z_table: &Zobrist) -> u64 {
+        let (sr, sc, er, ec) = m.coords(self.geo.size);
+        let n = self.geo.size;
+        let src = self.idx(sr, sc);
+        let move_mask = Bitboard::from_index(src) | Bitboard::from_index(self.idx(er, ec));
         
         let mut h = self.hash;
 
         // Identify piece.
-        let p_idx = if (self.black_pieces & src_mask) != 0 { 0 }
-                    else if (self.white_pieces & src_mask) != 0 { 1 }
-                    else if (self.king_piece & src_mask) != 0 { 2 }
+        let p_idx = if self.black_pieces.get(src) { 0 }
+                    else if self.white_pieces.get(src) { 1 }
+                    else if self.king_piece.get(src) { 2 }
                     // Safety check (though engine shouldn't pass empty squares).
                     else { return self.hash; };
 
@@ -182,16 +609,19 @@ impl GameState {
         else if p_idx == 1 { sim_white ^= move_mask; }
         else { sim_king ^= move_mask; }
 
+        // An unarmed king does not capture.
+        if p_idx == 2 && !self.rules.king_armed { return h; }
+
         let mover_is_black = p_idx == 0;
-        let dst_idx = Self::idx(er, ec);
+        let dst_idx = self.idx(er, ec);
 
         // Check the four neighbors.
         let neighbors = self.get_orthogonal_neighbors(dst_idx);
         for &victim_idx in &neighbors {
             // Check if this neighbor is an enemy.
-            let is_victim_black = (sim_black & (1 << victim_idx)) != 0;
-            let is_victim_white = (sim_white & (1 << victim_idx)) != 0;
-            let is_victim_king = (sim_king & (1 << victim_idx)) != 0;
+            let is_victim_black = sim_black.get(victim_idx);
+            let is_victim_white = sim_white.get(victim_idx);
+            let is_victim_king = sim_king.get(victim_idx);
             if !is_victim_black && !is_victim_white && !is_victim_king { continue; }
             // Define Enemy/Friend based on Mover
             let is_enemy = if mover_is_black { is_victim_white || is_victim_king } 
@@ -201,7 +631,7 @@ impl GameState {
             // King capture.
             if is_victim_king {
                 if self.check_king_captured_sim(sim_black, sim_king) {
-                    h ^= z_table.table[victim_idx/7][victim_idx%7][2];
+                    h ^= z_table.table[victim_idx/n][victim_idx%n][2];
                 }
                 continue;
             }
@@ -211,84 +641,67 @@ impl GameState {
                 // Check if Anvil is hostile to the victim
                 if self.is_hostile_sim(anvil_idx, is_victim_black, sim_black, sim_white, sim_king) {
                     // Capture!
-                    let v_r = victim_idx / 7;
-                    let v_c = victim_idx % 7;
+                    let v_r = victim_idx / n;
+                    let v_c = victim_idx % n;
                     let v_pidx = if is_victim_black { 0 } else { 1 };
                     h ^= z_table.table[v_r][v_c][v_pidx];
                 }
             }
         }
 
+        // Shieldwall captures (removed as a group).
+        let v_pidx = if mover_is_black { 1 } else { 0 };
+        for v_idx in self.shieldwall_captures(dst_idx, mover_is_black, sim_black, sim_white, sim_king).iter() {
+            h ^= z_table.table[v_idx/n][v_idx%n][v_pidx];
+        }
+
         h
     }
 
     /// Get neighbors as indices (up to four).
-    #[inline]
-    fn get_orthogonal_neighbors(&self, idx: usize) -> Vec<usize> {
-        let mut n = Vec::with_capacity(4);
-        let r = idx / 7;
-        let c = idx % 7;
-        
-        if r > 0 { n.push(idx - 7); } // North
-        if r < 6 { n.push(idx + 7); } // South
-        if c > 0 { n.push(idx - 1); } // West
-        if c < 6 { n.push(idx + 1); } // East
-        
-        n
+    #[inline(always)]
+    fn get_orthogonal_neighbors(&self, idx: usize) -> Neighbors {
+        self.geo.neighbors(idx)
     }
 
     /// Helper for King Capture in simulation.
-    fn check_king_captured_sim(&self, black: u64, king: u64) -> bool {
-        let k_idx = king.trailing_zeros() as usize; // Get king index
-        if k_idx >= 64 { return false; } // Should not happen if king exists
+    fn check_king_captured_sim(&self, black: Bitboard, king: Bitboard) -> bool {
+        // Get king index
+        let Some(k_idx) = king.first() else { return false; }; // Should not happen if king exists
 
         let neighbors = self.get_orthogonal_neighbors(k_idx);
-        
-        // If on Throne (24), needs 4 attackers
-        if k_idx == 24 {
-            if neighbors.len() < 4 { return false; } // Should be 4
-            for n in neighbors {
-                if (black & (1 << n)) == 0 { return false; }
-            }
-            return true;
-        }
-
-        // If next to Throne (Right, Left, Up, Down of 24)
-        // 25, 23, 17, 31
-        let is_next_throne = k_idx == 23 || k_idx == 25 || k_idx == 17 || k_idx == 31;
-        if is_next_throne {
-            // Needs 3 attackers + Throne acting as anvil
-            for n in neighbors {
-                // If neighbor is throne, it counts as hostile
-                if n == 24 { continue; }
-                // Otherwise needs black piece
-                if (black & (1 << n)) == 0 { return false; }
+        let throne_idx = self.geo.throne_idx;
+
+        // Squares hostile to the king: black pieces, corners and the (empty) throne, depending on the rules.
+        let is_hostile = |n: usize| -> bool {
+            black.get(n)
+            || (self.rules.hostile_corners && self.geo.corners.get(n))
+            || (self.rules.empty_throne_hostile_to_white && n == throne_idx)
+        };
+        // Surrounded on all four sides (hostile squares count).
+        let surrounded = || neighbors.len() == 4 && neighbors.iter().all(|&n| is_hostile(n));
+
+        match self.rules.king_capture {
+            KingCapture::FourSides => return surrounded(),
+            // If on Throne, needs 4 attackers.
+            // If next to Throne, needs 3 attackers + Throne acting as anvil.
+            KingCapture::Standard if k_idx == throne_idx || self.geo.is_next_to_throne(k_idx) => {
+                return surrounded();
             }
-            return true;
+            _ => {}
         }
 
         // Standard capture (2 sides)
         // Check horizontal pair
-        let r = k_idx / 7;
-        let c = k_idx % 7;
+        let size = self.geo.size;
+        let r = k_idx / size;
+        let c = k_idx % size;
         
         // Check Horizontal (West/East)
-        if c > 0 && c < 6 {
-            let w = k_idx - 1;
-            let e = k_idx + 1;
-            let w_hostile = ((black & (1<<w)) != 0) || ((CORNERS & (1<<w)) != 0);
-            let e_hostile = ((black & (1<<e)) != 0) || ((CORNERS & (1<<e)) != 0);
-            if w_hostile && e_hostile { return true; }
-        }
+        if c > 0 && c < size - 1 && is_hostile(k_idx - 1) && is_hostile(k_idx + 1) { return true; }
 
         // Check Vertical (North/South)
-        if r > 0 && r < 6 {
-            let n = k_idx - 7;
-            let s = k_idx + 7;
-            let n_hostile = ((black & (1<<n)) != 0) || ((CORNERS & (1<<n)) != 0);
-            let s_hostile = ((black & (1<<s)) != 0) || ((CORNERS & (1<<s)) != 0);
-            if n_hostile && s_hostile { return true; }
-        }
+        if r > 0 && r < size - 1 && is_hostile(k_idx - size) && is_hostile(k_idx + size) { return true; }
 
         false
     }
@@ -297,16 +710,17 @@ impl GameState {
     /// Src -> Victim -> Anvil
     #[inline]
     fn get_anvil_index(&self, src: usize, victim: usize) -> Option<usize> {
+        let size = self.geo.size;
         let diff = victim as isize - src as isize;
-        // diff is -7, +7, -1, or +1
+        // diff is -size, +size, -1, or +1
         let anvil = victim as isize + diff;
         
         // Bounds check
-        if !(0..49).contains(&anvil) { return None; }
+        if !(0..self.geo.squares as isize).contains(&anvil) { return None; }
         
         // Check row wrapping for horizontal moves
-        let v_c = victim % 7;
-        let a_c = anvil as usize % 7;
+        let v_c = victim % size;
+        let a_c = anvil as usize % size;
         
         // If moving horizontal (diff 1 or -1), col distance must be 1
         if diff.abs() == 1 && (v_c as isize - a_c as isize).abs() != 1 {
@@ -318,58 +732,152 @@ impl GameState {
 
     /// Check if a square is hostile to a victim (Simulated version for next_hash/move)
     #[inline]
-    fn is_hostile_sim(&self, idx: usize, victim_is_black: bool, b: u64, w: u64, k: u64) -> bool {
-        let mask = 1 << idx;
-        let occupied_black = (b & mask) != 0;
-        let occupied_white = (w & mask) != 0;
-        let occupied_king = (k & mask) != 0;
+    fn is_hostile_sim(&self, idx: usize, victim_is_black: bool, b: Bitboard, w: Bitboard, k: Bitboard) -> bool {
+        let occupied_black = b.get(idx);
+        let occupied_white = w.get(idx);
+        let occupied_king = k.get(idx);
+        let is_corner = self.geo.corners.get(idx);
+        let is_throne = idx == self.geo.throne_idx;
         
         // 1. Piece Hostility
         if victim_is_black {
-            // Hostile if occupied by White or King
-            if occupied_white || occupied_king { return true; }
+            // Hostile if occupied by White or (armed) King
+            if occupied_white || (occupied_king && self.rules.king_armed) { return true; }
             // Or if it's a corner
-            if (CORNERS & mask) != 0 { return true; }
-            // Or Throne (always hostile to black)
-            if (THRONE & mask) != 0 { return true; }
+            if is_corner && self.rules.hostile_corners { return true; }
+            // Or Throne (hostile to black even when occupied)
+            if is_throne && self.rules.throne_hostile_to_black { return true; }
         } else {
             // Victim is White
             // Hostile if occupied by Black
             if occupied_black { return true; }
-            // Corners hostile to everyone
-            if (CORNERS & mask) != 0 { return true; }
+            // Corners
+            if is_corner && self.rules.hostile_corners { return true; }
             // Throne hostile to white ONLY if empty (King left it)
-            if (THRONE & mask) != 0 && !occupied_king { return true; }
+            if is_throne && !occupied_king && self.rules.empty_throne_hostile_to_white { return true; }
         }
         false
     }
 
+    /// Shieldwall captures (rule 4b) made by a piece landing on dst_idx, on the simulated boards.
+    /// A row of two or more enemy pieces along the edge is captured if it is bracketed at both ends
+    /// (by the mover and by a friendly piece or a hostile corner) and every piece of the row
+    /// has a friendly piece in front of it.
+    /// Returns the captured pawns: the king can be part of the row, but is not captured.
+    fn shieldwall_captures(&self, dst_idx: usize, mover_is_black: bool, b: Bitboard, w: Bitboard, k: Bitboard) -> Bitboard {
+        let mut captured = Bitboard::EMPTY;
+        if !self.rules.shieldwall { return captured; }
+
+        let n = self.geo.size as isize;
+        let last = n - 1;
+        let (r, c) = ((dst_idx / self.geo.size) as isize, (dst_idx % self.geo.size) as isize);
+
+        // Pieces that can bracket or block the front of the row, and pieces that can be in the row.
+        let friends = if mover_is_black { b } else if self.rules.king_armed { w | k } else { w };
+        let enemies = if mover_is_black { w | k } else { b };
+        let in_board = |rr: isize, cc: isize| (0..n).contains(&rr) && (0..n).contains(&cc);
+        let to_idx = |rr: isize, cc: isize| (rr * n + cc) as usize;
+
+        // (mover on this edge, step along the edge, step towards the front of the row)
+        let edges = [
+            (r == 0, (0, 1), (1, 0)),
+            (r == last, (0, 1), (-1, 0)),
+            (c == 0, (1, 0), (0, 1)),
+            (c == last, (1, 0), (0, -1)),
+        ];
+        for (on_edge, (dr, dc), (fr, fc)) in edges {
+            if !on_edge { continue; }
+            // Walk in both directions along the edge.
+            for sign in [-1, 1] {
+                let mut row = Bitboard::EMPTY;
+                let mut row_len = 0;
+                let (mut rr, mut cc) = (r + sign * dr, c + sign * dc);
+                while in_board(rr, cc) && enemies.get(to_idx(rr, cc)) {
+                    // Every piece of the row must be blocked from the front.
+                    if !friends.get(to_idx(rr + fr, cc + fc)) { row_len = 0; break; }
+                    row.set(to_idx(rr, cc));
+                    row_len += 1;
+                    rr += sign * dr;
+                    cc += sign * dc;
+                }
+                if row_len < 2 || !in_board(rr, cc) { continue; }
+
+                // The other end must be bracketed.
+                let end = to_idx(rr, cc);
+                if friends.get(end) || (self.rules.hostile_corners && self.geo.corners.get(end)) {
+                    captured |= row;
+                }
+            }
+        }
+
+        // The king is not captured by a shieldwall.
+        captured & !k
+    }
+
     // ========================
     //      MOVE EXECUTION
     // ========================
 
     /// Move piece on the board and update hash and history.
     /// The logic assumes the move to be legal.
-    /// coords = [start_row, start_col, end_row, end_col]
+    /// Returns the pieces the move captured and whether it caused a repetition.
     #[inline]
-    pub fn move_piece<W: Write>(&mut self, coords: &[usize; 4], z_table: &Zobrist, is_sim_move: bool, writer: &mut W) {
-        let (sr, sc, er, ec) = (coords[0], coords[1], coords[2], coords[3]);
-        let src_mask = 1 << Self::idx(sr, sc);
-        let dst_mask = 1 << Self::idx(er, ec);
-        let move_mask = src_mask | dst_mask;
+    pub fn move_piece<W: Write>(&mut self, m: &Move, z_table: &Zobrist, is_sim_move: bool, writer: &mut W) -> MoveOutcome {
+        let undo = self.make_move(m, z_table, is_sim_move, writer);
+        self.move_outcome(&undo)
+    }
+
+    /// Outcome of the last move made, from its undo record.
+    pub fn move_outcome(&self, undo: &Undo) -> MoveOutcome {
+        let n = self.geo.size;
+        let captured = if undo.mover == 0 { Piece::White } else { Piece::Black };
+        let mut captures: Vec<Capture> = undo.captured.iter()
+            .map(|i| Capture { row: i / n, col: i % n, piece: captured })
+            .collect();
+        if let Some(k_idx) = undo.captured_king {
+            let k_idx = k_idx as usize;
+            captures.push(Capture { row: k_idx / n, col: k_idx % n, piece: Piece::King });
+            captures.sort_by_key(|capture| (capture.row, capture.col));
+        }
+        MoveOutcome {
+            captures,
+            king_captured: undo.captured_king.is_some(),
+            repetition: self.repetition,
+        }
+    }
+
+    /// Same as move_piece, but returns the record needed to take the move back with unmake_move.
+    #[inline]
+    pub fn make_move<W: Write>(&mut self, m: &Move, z_table: &Zobrist, is_sim_move: bool, writer: &mut W) -> Undo {
+        let (sr, sc, er, ec) = m.coords(self.geo.size);
+        let src = self.idx(sr, sc);
+        let dst = self.idx(er, ec);
+        let move_mask = Bitboard::from_index(src) | Bitboard::from_index(dst);
+
+        let mut undo = Undo {
+            src: src as u8,
+            dst: dst as u8,
+            mover: 0,
+            captured: Bitboard::EMPTY,
+            captured_king: None,
+            prev_hash: self.hash,
+            prev_repetition: self.repetition,
+            prev_repetition_dist: self.repetition_dist,
+            prev_window_start: self.history.window_start as u32,
+        };
 
         // Update ply count.
         self.ply_count += 1;
 
         // Update board.
         let mut p_idx = 0; // 0:B, 1:W, 2:K
-        if (self.black_pieces & src_mask) != 0 {
+        if self.black_pieces.get(src) {
             self.black_pieces ^= move_mask;
             p_idx = 0;
-        } else if (self.white_pieces & src_mask) != 0 {
+        } else if self.white_pieces.get(src) {
             self.white_pieces ^= move_mask;
             p_idx = 1;
-        } else if (self.king_piece & src_mask) != 0 {
+        } else if self.king_piece.get(src) {
             self.king_piece ^= move_mask;
             p_idx = 2;
         }
@@ -380,56 +888,79 @@ impl GameState {
         self.hash ^= z_table.black_to_move;
         
         // Apply captures.
-        self.apply_captures_bits(er, ec, p_idx, z_table, is_sim_move, writer);
-
-        // Update history (Sorted Insert).
-        let current_state_key = (self.black_pieces, self.white_pieces, self.king_piece);
-        if self.history_len < MAX_GAME_LENGTH {
-            let slice = &self.history[0..self.history_len];
-            let res = slice.binary_search_by(|entry| {
-                entry.0.cmp(&current_state_key.0)
-                    .then(entry.1.cmp(&current_state_key.1))
-                    .then(entry.2.cmp(&current_state_key.2))
-            });
+        undo.mover = p_idx as u8;
+        (undo.captured, undo.captured_king) = self.apply_captures_bits(er, ec, p_idx, z_table, is_sim_move, writer);
 
-            match res {
-                Ok(idx) => {
-                    self.repetition = true;
-                    // Retrieve when this state occurred.
-                    let old_ply = self.history[idx].3;
-                    self.repetition_dist = Some(self.ply_count - old_ply);
-                }
-                Err(idx) => {
-                    self.repetition = false;
-                    self.repetition_dist = None;
-                    // Shift history.
-                    if idx < self.history_len {
-                        self.history.copy_within(idx..self.history_len, idx + 1);
-                    }
-                    // Insert new state.
-                    self.history[idx] = (self.black_pieces, self.white_pieces, self.king_piece, self.ply_count);
-                    self.history_len += 1;
-                }
-            }
-        }
-        
         // Update player.
-        self.player = if self.player == 'B' { 'W' } else { 'B' };
+        self.player = self.player.opponent();
+
+        // Update history.
+        // A capture starts a new window: no earlier position can occur again.
+        if undo.is_capture() { self.history.window_start = self.history.entries.len(); }
+        let boards = (self.black_pieces, self.white_pieces, self.king_piece);
+        let key = if self.player == Player::Black { self.hash ^ z_table.black_to_move } else { self.hash };
+        // The latest occurrence of this state tells how many times it occurred and when it first did.
+        let (occurrence, first_ply) = self.history.latest(key, boards, self.player, self.rules.repetition_side_to_move)
+            .map_or((1, self.ply_count as u32), |old| (old.occurrence + 1, old.first_ply));
+        self.repetition = occurrence as usize >= self.rules.repetition_count;
+        self.repetition_dist = self.repetition.then(|| self.ply_count - first_ply as usize);
+        self.history.push(HistoryEntry {
+            key, black: boards.0, white: boards.1, king: boards.2, player: self.player,
+            occurrence, first_ply, prev_same: 0,
+        });
+
+        undo
+    }
+
+    /// Take back a move made with make_move.
+    /// Moves have to be taken back in the reverse order they were made.
+    pub fn unmake_move(&mut self, undo: &Undo) {
+        let (src, dst) = (undo.src as usize, undo.dst as usize);
+        let move_mask = Bitboard::from_index(src) | Bitboard::from_index(dst);
+
+        // Restore player.
+        self.player = self.player.opponent();
+
+        // Remove the state from the history, and bring back the window a capture closed.
+        self.history.pop();
+        self.history.window_start = undo.prev_window_start as usize;
+
+        // Restore captured pieces.
+        if undo.mover == 0 { self.white_pieces |= undo.captured; }
+        else { self.black_pieces |= undo.captured; }
+        if let Some(k_idx) = undo.captured_king { self.king_piece.set(k_idx as usize); }
+
+        // Move the piece back.
+        match undo.mover {
+            0 => self.black_pieces ^= move_mask,
+            1 => self.white_pieces ^= move_mask,
+            _ => self.king_piece ^= move_mask,
+        }
+
+        self.hash = undo.prev_hash;
+        self.repetition = undo.prev_repetition;
+        self.repetition_dist = undo.prev_repetition_dist;
+        self.ply_count -= 1;
     }
 
     /// Apply all captures.
+    /// Returns the captured pawns and the square of the king if he was captured.
     #[inline]
-    fn apply_captures_bits<W: Write>(&mut self, r: usize, c: usize, mover_type: usize, z_table: &Zobrist, is_sim_move: bool, writer: &mut W) {
+    fn apply_captures_bits<W: Write>(&mut self, r: usize, c: usize, mover_type: usize, z_table: &Zobrist, is_sim_move: bool, writer: &mut W) -> (Bitboard, Option<u8>) {
         // mover_type: 0=B, 1=W, 2=K
-        let dst_idx = Self::idx(r, c);
+        let mut captured = Bitboard::EMPTY;
+        let mut captured_king = None;
+        // An unarmed king does not capture.
+        if mover_type == 2 && !self.rules.king_armed { return (captured, captured_king); }
+        let n = self.geo.size;
+        let dst_idx = self.idx(r, c);
 
         // Check the four neighbors.
         let neighbors = self.get_orthogonal_neighbors(dst_idx);
         for &v_idx in &neighbors {
-            let mask = 1 << v_idx;
-            let is_b = (self.black_pieces & mask) != 0;
-            let is_w = (self.white_pieces & mask) != 0;
-            let is_k = (self.king_piece & mask) != 0;
+            let is_b = self.black_pieces.get(v_idx);
+            let is_w = self.white_pieces.get(v_idx);
+            let is_k = self.king_piece.get(v_idx);
 
             if !is_b && !is_w && !is_k { continue; }
 
@@ -440,8 +971,9 @@ impl GameState {
             // King Capture.
             if is_k {
                 if self.check_king_captured_sim(self.black_pieces, self.king_piece) {
-                    self.king_piece &= !mask; // Remove King from board.
-                    self.hash ^= z_table.table[v_idx/7][v_idx%7][2];
+                    self.king_piece.clear(v_idx); // Remove King from board.
+                    self.hash ^= z_table.table[v_idx/n][v_idx%n][2];
+                    captured_king = Some(v_idx as u8);
                     if !is_sim_move { writeln!(writer, "King got captured").expect("could not write to output"); }
                 }
                 continue;
@@ -451,17 +983,37 @@ impl GameState {
             if let Some(anvil_idx) = self.get_anvil_index(dst_idx, v_idx)
             && self.is_hostile_sim(anvil_idx, is_b, self.black_pieces, self.white_pieces, self.king_piece) {
                 // Remove Piece and update hash.
+                captured.set(v_idx);
                 if is_b { 
-                    self.black_pieces &= !mask; 
-                    self.hash ^= z_table.table[v_idx/7][v_idx%7][0];
+                    self.black_pieces.clear(v_idx); 
+                    self.hash ^= z_table.table[v_idx/n][v_idx%n][0];
                     if !is_sim_move { writeln!(writer, "Black piece got captured").expect("could not write to output"); }
                 } else { 
-                    self.white_pieces &= !mask;
-                    self.hash ^= z_table.table[v_idx/7][v_idx%7][1];
+                    self.white_pieces.clear(v_idx);
+                    self.hash ^= z_table.table[v_idx/n][v_idx%n][1];
                     if !is_sim_move { writeln!(writer, "White piece got captured").expect("could not write to output"); }
                 }
             }
         }
+
+        // Shieldwall Captures.
+        let mover_is_black = mover_type == 0;
+        let wall = self.shieldwall_captures(dst_idx, mover_is_black, self.black_pieces, self.white_pieces, self.king_piece);
+        for v_idx in wall.iter() {
+            if mover_is_black {
+                self.white_pieces.clear(v_idx);
+                self.hash ^= z_table.table[v_idx/n][v_idx%n][1];
+            } else {
+                self.black_pieces.clear(v_idx);
+                self.hash ^= z_table.table[v_idx/n][v_idx%n][0];
+            }
+        }
+        if !is_sim_move && !wall.is_empty() {
+            let color = if mover_is_black { "White" } else { "Black" };
+            writeln!(writer, "{} {} piece(s) got captured by a shieldwall", wall.count_ones(), color).expect("could not write to output");
+        }
+
+        (captured | wall, captured_king)
     }
 
     // =========================
@@ -470,179 +1022,148 @@ impl GameState {
     
     /// Return true if the given player has at least one legal move.
     /// Function called only by check_game_over()
-    fn has_legal_move(&self, player: char) -> bool {
+    fn has_legal_move(&self, player: Player, z_table: &Zobrist) -> bool {
         let occupied = self.black_pieces | self.white_pieces | self.king_piece;
         
-        let my_pieces = if player == 'B' { self.black_pieces } 
+        let my_pieces = if player == Player::Black { self.black_pieces } 
                         else { self.white_pieces | self.king_piece };
 
-        // We only check repetition if the player is White (Rule 8).
-        let check_repetition = player == 'W';
-        let history_slice = &self.history[0..self.history_len];
+        // We only check repetition if the player is White and repeating loses (Rule 8).
+        let check_repetition = player == Player::White && self.rules.repetition == RepetitionRule::WhiteLoses;
 
         // Closure to check if a specific move is valid regarding repetition
         let is_safe_move = |r, c, er, ec| -> bool {
-            if !check_repetition { return true; }
-
-            let coords = [r, c, er, ec];
-            let (nb, nw, nk) = self.predict_next_boards(&coords);
-            let target_key = (nb, nw, nk);
+            !check_repetition || !self.repeats_history(&Move::new(r, c, er, ec, self.geo.size), z_table)
+        };
 
-            // Binary search in history to see if this state existed before
-            let found = history_slice.binary_search_by(|entry| {
-                entry.0.cmp(&target_key.0)
-                    .then(entry.1.cmp(&target_key.1))
-                    .then(entry.2.cmp(&target_key.2))
-            }).is_ok();
+        let n = self.geo.size;
 
-            !found // Valid if NOT found
-        };
+        // Fast path: one step to an empty neighbor the piece may stop on.
+        // Without repetition check, the first such move is enough.
+        let empty = self.geo.board & !occupied;
+        for i in my_pieces.iter() {
+            let allowed = empty & self.allowed_destinations(i);
+            for d in self.get_orthogonal_neighbors(i) {
+                if allowed.get(d) && is_safe_move(i / n, i % n, d / n, d % n) { return true; }
+            }
+        }
 
-        for i in 0..TOTAL_SQUARES {
-            // If I have a piece at i
-            if (my_pieces & (1 << i)) != 0 {
-                let r = i / 7;
-                let c = i % 7;
-                
-                // Try directions
-                // UP
-                for rr in (0..r).rev() {
-                    let dest = Self::idx(rr, c);
-                    if (occupied & (1 << dest)) != 0 { break; } // Blocked
-                    if !self.is_restricted_violation(rr, c, i)
-                    && is_safe_move(r, c, rr, c) {
-                        return true;
-                    }
-                }
-                // DOWN
-                for rr in r+1..7 {
-                    let dest = Self::idx(rr, c);
-                    if (occupied & (1 << dest)) != 0 { break; }
-                    if !self.is_restricted_violation(rr, c, i)
-                    && is_safe_move(r, c, rr, c) {
-                        return true;
-                    }
-                }
-                // LEFT
-                for cc in (0..c).rev() {
-                    let dest = Self::idx(r, cc);
-                    if (occupied & (1 << dest)) != 0 { break; }
-                    if !self.is_restricted_violation(r, cc, i)
-                    && is_safe_move(r, c, r, cc) {
-                        return true;
-                    }
-                }
-                // RIGHT
-                for cc in c+1..7 {
-                    let dest = Self::idx(r, cc);
-                    if (occupied & (1 << dest)) != 0 { break; }
-                    if !self.is_restricted_violation(r, cc, i)
-                    && is_safe_move(r, c, r, cc) {
-                        return true;
-                    }
+        // For each of my pieces.
+        let occupied = self.geo.lines(occupied);
+        let restricted = self.geo.lines(self.geo.restricted);
+        for r in 0..n {
+            for c in LineIter(my_pieces.bits(r * n, n)) {
+                let [up, down, left, right] = self.piece_slides(r, c, &occupied, &restricted);
+                // All the destinations, for the moves the fast path could not find
+                // (longer slides, e.g. over the empty throne, or repetitions).
+                if LineIter(up | down).any(|rr| is_safe_move(r, c, rr, c))
+                || LineIter(left | right).any(|cc| is_safe_move(r, c, r, cc)) {
+                    return true;
                 }
             }
         }
         false
     }
 
-    /// Modify in place the vector of legal moves from the current state.
-    /// Avoids allocating a vector each time (the function is called multiple times during Simulation).
-    /// Algorithm from has_legal_move() modified to guarantee that indices are usize (and avoid casting).
-    /// If no_repetition is true and player is White, avoids moves that cause history repetition.
-    pub fn get_legal_moves(&self, moves: &mut Vec<[usize; 4]>, no_repetition: bool) {
+    /// Fill in place the list of legal moves from the current state.
+    /// The list lives on the stack of the caller, so no allocation happens (the function is called multiple times during Simulation).
+    /// The destinations of each piece are computed on its row and column with shifts and masks (see line_slides).
+    /// If no_repetition is true, player is White and repeating loses, avoids moves that cause history repetition.
+    pub fn get_legal_moves(&self, moves: &mut MoveList, no_repetition: bool, z_table: &Zobrist) {
         moves.clear();
         let occupied = self.black_pieces | self.white_pieces | self.king_piece;
-        let my_pieces = if self.player == 'B' { self.black_pieces } 
+        let my_pieces = if self.player == Player::Black { self.black_pieces } 
                         else { self.white_pieces | self.king_piece };
 
-        let filter_repetition = no_repetition && self.player == 'W';
+        let filter_repetition = no_repetition && self.player == Player::White
+                                && self.rules.repetition == RepetitionRule::WhiteLoses;
 
         // Function used below.
         let mut add_move = |r, c, er, ec| {
-            // Repetition check logic.
-            if filter_repetition {
-                let coords = [r, c, er, ec];
-                let (nb, nw, nk) = self.predict_next_boards(&coords);
-                
-                // Binary search in history.
-                let target_key = (nb, nw, nk);
-                let slice = &self.history[0..self.history_len];
-                let found = slice.binary_search_by(|entry| {
-                    entry.0.cmp(&target_key.0)
-                        .then(entry.1.cmp(&target_key.1))
-                        .then(entry.2.cmp(&target_key.2))
-                }).is_ok();
-
-                // If the move is a repetition, we skip it.
-                if found { return; }
-            }
-
-            moves.push([r, c, er, ec]);
+            let m = Move::new(r, c, er, ec, self.geo.size);
+            // If the move is a repetition, we skip it.
+            if filter_repetition && self.repeats_history(&m, z_table) { return; }
+            moves.push(m);
         };
 
-        for i in 0..TOTAL_SQUARES {
-            if (my_pieces & (1 << i)) != 0 {
-                let r = i / 7;
-                let c = i % 7;
-                
+        let n = self.geo.size;
+        let occupied = self.geo.lines(occupied);
+        let restricted = self.geo.lines(self.geo.restricted);
+        for r in 0..n {
+            for c in LineIter(my_pieces.bits(r * n, n)) {
+                let [up, down, left, right] = self.piece_slides(r, c, &occupied, &restricted);
+
+                // Nearest destinations first.
                 // UP
-                if r > 0 {
-                    for rr in (0..r).rev() {
-                        let dest = Self::idx(rr, c);
-                        if (occupied & (1 << dest)) != 0 { break; } 
-                        if !self.is_restricted_violation(rr, c, i) { add_move(r, c, rr, c); }
-                    }
-                }
+                for rr in LineIter(up).rev() { add_move(r, c, rr, c); }
                 // DOWN
-                if r < 6 {
-                    for rr in r+1..7 {
-                        let dest = Self::idx(rr, c);
-                        if (occupied & (1 << dest)) != 0 { break; } 
-                        if !self.is_restricted_violation(rr, c, i) { add_move(r, c, rr, c); }
-                    }
-                }
+                for rr in LineIter(down) { add_move(r, c, rr, c); }
                 // LEFT
-                if c > 0 {
-                    for cc in (0..c).rev() {
-                        let dest = Self::idx(r, cc);
-                        if (occupied & (1 << dest)) != 0 { break; } 
-                        if !self.is_restricted_violation(r, cc, i) { add_move(r, c, r, cc); }
-                    }
-                }
+                for cc in LineIter(left).rev() { add_move(r, c, r, cc); }
                 // RIGHT
-                if c < 6 {
-                    for cc in c+1..7 {
-                        let dest = Self::idx(r, cc);
-                        if (occupied & (1 << dest)) != 0 { break; } 
-                        if !self.is_restricted_violation(r, cc, i) { add_move(r, c, r, cc); }
-                    }
-                }
+                for cc in LineIter(right) { add_move(r, c, r, cc); }
             }
         }
     }
 
+    /// True if the move recreates a state of the history often enough for the repetition rule to apply
+    /// (the same test as in make_move).
+    #[inline]
+    fn repeats_history(&self, m: &Move, z_table: &Zobrist) -> bool {
+        let boards = self.predict_next_boards(m);
+        // Key of the boards after the move: the squares that changed are toggled in the current key.
+        let n = self.geo.size;
+        let mut key = self.history.entries.last().expect("empty history").key;
+        let current = [self.black_pieces, self.white_pieces, self.king_piece];
+        for (p_idx, (before, after)) in current.into_iter().zip([boards.0, boards.1, boards.2]).enumerate() {
+            for i in (before ^ after).iter() { key ^= z_table.table[i / n][i % n][p_idx]; }
+        }
+        self.history.latest(key, boards, self.player.opponent(), self.rules.repetition_side_to_move)
+            .is_some_and(|old| old.occurrence as usize + 1 >= self.rules.repetition_count)
+    }
+
+    /// Squares the piece on src_idx may stop on: anywhere for the king, anything but the restricted squares otherwise.
+    #[inline(always)]
+    fn allowed_destinations(&self, src_idx: usize) -> Bitboard {
+        if self.king_piece.get(src_idx) { self.geo.board } else { !self.geo.restricted }
+    }
+
+    /// Squares the piece on (r, c) can move to, in each direction: [up, down, left, right].
+    /// Up and down are masks of column c (bit = row), left and right masks of row r (bit = column).
+    /// Only the king may stop on a restricted square.
+    #[inline(always)]
+    fn piece_slides(&self, r: usize, c: usize, occupied: &Lines, restricted: &Lines) -> [u32; 4] {
+        let n = self.geo.size;
+        let (mut up, mut down) = line_slides(occupied.cols[c], r, n);
+        let (mut left, mut right) = line_slides(occupied.rows[r], c, n);
+        if !self.king_piece.get(r * n + c) {
+            up &= !restricted.cols[c];
+            down &= !restricted.cols[c];
+            left &= !restricted.rows[r];
+            right &= !restricted.rows[r];
+        }
+        [up, down, left, right]
+    }
+
     /// Checks whether a piece different than the king is entering a restricted square.
     #[inline]
     fn is_restricted_violation(&self, r: usize, c: usize, src_idx: usize) -> bool {
-        let dest_mask = 1 << Self::idx(r, c);
         // If dest is not restricted, it's fine
-        if (RESTRICTED & dest_mask) == 0 { return false; }
+        if !self.geo.restricted.get(self.idx(r, c)) { return false; }
         
         // If dest IS restricted, only King can go there.
         // Check if the piece at src_idx is the King.
-        if (self.king_piece & (1 << src_idx)) != 0 { return false; }
+        if self.king_piece.get(src_idx) { return false; }
         
         true
     }
 
     /// Simulate what the boards would look like after a move (B, W, K).
     /// Used for checking repetitions without mutating state.
-    fn predict_next_boards(&self, coords: &[usize; 4]) -> (u64, u64, u64) {
-        let (sr, sc, er, ec) = (coords[0], coords[1], coords[2], coords[3]);
-        let src_mask = 1 << Self::idx(sr, sc);
-        let dst_mask = 1 << Self::idx(er, ec);
-        let move_mask = src_mask | dst_mask;
+    fn predict_next_boards(&self, m: &Move) -> (Bitboard, Bitboard, Bitboard) {
+        let (sr, sc, er, ec) = m.coords(self.geo.size);
+        let src = self.idx(sr, sc);
+        let move_mask = Bitboard::from_index(src) | Bitboard::from_index(self.idx(er, ec));
 
         let mut next_black = self.black_pieces;
         let mut next_white = self.white_pieces;
@@ -650,29 +1171,30 @@ impl GameState {
 
         // 1. Move the piece
         let mut p_idx = 0; // 0:B, 1:W, 2:K
-        if (self.black_pieces & src_mask) != 0 {
+        if self.black_pieces.get(src) {
             next_black ^= move_mask;
             p_idx = 0;
-        } else if (self.white_pieces & src_mask) != 0 {
+        } else if self.white_pieces.get(src) {
             next_white ^= move_mask;
             p_idx = 1;
-        } else if (self.king_piece & src_mask) != 0 {
+        } else if self.king_piece.get(src) {
             next_king ^= move_mask;
             p_idx = 2;
         }
 
+        // An unarmed king does not capture.
+        if p_idx == 2 && !self.rules.king_armed { return (next_black, next_white, next_king); }
+
         let mover_is_black = p_idx == 0;
-        let dst_idx = Self::idx(er, ec);
+        let dst_idx = self.idx(er, ec);
 
         // 2. Apply Captures (Logic adapted from next_hash)
         let neighbors = self.get_orthogonal_neighbors(dst_idx);
         for &victim_idx in &neighbors {
-            let v_mask = 1 << victim_idx;
-            
             // Check if neighbor is occupied in the SIMULATED board
-            let is_victim_black = (next_black & v_mask) != 0;
-            let is_victim_white = (next_white & v_mask) != 0;
-            let is_victim_king = (next_king & v_mask) != 0;
+            let is_victim_black = next_black.get(victim_idx);
+            let is_victim_white = next_white.get(victim_idx);
+            let is_victim_king = next_king.get(victim_idx);
 
             if !is_victim_black && !is_victim_white && !is_victim_king { continue; }
 
@@ -684,7 +1206,7 @@ impl GameState {
             // King capture check
             if is_victim_king {
                 if self.check_king_captured_sim(next_black, next_king) {
-                    next_king &= !v_mask; // Remove King
+                    next_king.clear(victim_idx); // Remove King
                 }
                 continue;
             }
@@ -693,11 +1215,16 @@ impl GameState {
             if let Some(anvil_idx) = self.get_anvil_index(dst_idx, victim_idx)
             && self.is_hostile_sim(anvil_idx, is_victim_black, next_black, next_white, next_king) {
                 // Remove victim
-                if is_victim_black { next_black &= !v_mask; }
-                else { next_white &= !v_mask; }
+                if is_victim_black { next_black.clear(victim_idx); }
+                else { next_white.clear(victim_idx); }
             }
         }
 
+        // 3. Shieldwall Captures
+        let wall = self.shieldwall_captures(dst_idx, mover_is_black, next_black, next_white, next_king);
+        if mover_is_black { next_white &= !wall; }
+        else { next_black &= !wall; }
+
         (next_black, next_white, next_king)
     }
 
@@ -705,68 +1232,300 @@ impl GameState {
     //            GAME OVER
     // ===============================
 
-    /// Check if game is over, given a state and a move.
-    /// Returns:
-    /// None - Game is not over
-    /// W - White wins
-    /// B - Black wins
-    /// D - Draw
-    pub fn check_game_over(&self) -> Option<char> {
-        // === Check if King is at a corner => White wins ===
-        if (self.king_piece & CORNERS) != 0 { return Some('W'); }
+    /// Check if game is over.
+    /// Returns None if the game is not over, otherwise the winner (None for a draw) and the reason.
+    pub fn check_game_over(&self, z_table: &Zobrist) -> Option<GameOutcome> {
+        // === Check if King is at a corner (or edge) => White wins ===
+        if self.king_escaped() { return Some(GameOutcome::win(Player::White, EndReason::KingEscaped)); }
 
         // === Check if King is captured => Black wins ===
         // We rely on the fact that if the King was captured,
         // he was removed from the board in apply_captures.
-        if self.king_piece == 0 { return Some('B'); }
+        if self.king_piece.is_empty() { return Some(GameOutcome::win(Player::Black, EndReason::KingCaptured)); }
+
+        // === Rule 6b: Exit fort => White wins ===
+        if self.is_exit_fort() { return Some(GameOutcome::win(Player::White, EndReason::ExitFort)); }
+
+        // === Rule 7b: All white pieces surrounded => Black wins ===
+        if self.is_surrounded() { return Some(GameOutcome::win(Player::Black, EndReason::Surrounded)); }
 
-        // === Rule 8: Repetition => Black wins (White loses) ===
+        // === Rule 8: Repetition => Black wins (White loses) or Draw ===
         // We check if the current board exists previously in the history.
-        if self.repetition { return Some('B'); }
+        if let Some(result) = self.repetition_result() { return Some(result); }
 
         // === Rule 9: If the player to move has no legal move, he loses. ===
-        if !self.has_legal_move(self.player) {
-            let winner = if self.player == 'B' { 'W' } else { 'B' };
-            return Some(winner);
+        if !self.has_legal_move(self.player, z_table) {
+            return Some(GameOutcome::win(self.player.opponent(), EndReason::NoLegalMoves));
         }
 
         // === Rule 10: Draw due to "impossible to end the game" / insufficient material ===
-        if self.is_insufficient_material_draw() { return Some('D'); }
+        if self.is_insufficient_material_draw() { return Some(GameOutcome::draw(EndReason::InsufficientMaterial)); }
 
         None
     }
     /// Same as above, but prints the repetition distance.
     /// Used only for the actual game being played, for analysis purposes.
-    pub fn check_game_over_log<W: Write>(&self, writer: &mut W) -> Option<char> {
-        if (self.king_piece & CORNERS) != 0 { return Some('W'); }
-        if self.king_piece == 0 { return Some('B'); }
-        if self.repetition {
-            if let Some(dist) = self.repetition_dist {
-                // Only print if distance in full moves (plies / 2) is > 3.
-                writeln!(writer, "Repetition detected! The state first occurred {} plies ago.", dist).expect("Could not write repetition message to buffer.");
+    pub fn check_game_over_log<W: Write>(&self, z_table: &Zobrist, writer: &mut W) -> Option<GameOutcome> {
+        let outcome = self.check_game_over(z_table)?;
+        if outcome.reason == EndReason::Repetition
+        && let Some(dist) = self.repetition_dist {
+            writeln!(writer, "Repetition detected! The state first occurred {} plies ago.", dist).expect("Could not write repetition message to buffer.");
+        }
+        Some(outcome)
+    }
+
+    /// Rule 6b: the king is on the edge, is able to move, and is enclosed by white pieces
+    /// that black can never capture.
+    /// The fort interior is flood filled from the king through the non-white squares: it must not
+    /// contain black pieces. Every wall piece (white pawn next to the interior) must be safe: on both axes,
+    /// one side is the edge, another safe white pawn or a non-hostile interior square.
+    /// Safety is worked out over all the white pawns, since a wall piece may lean on pawns outside the fort.
+    fn is_exit_fort(&self) -> bool {
+        if !self.rules.exit_forts { return false; }
+        let Some(k_idx) = self.king_piece.first() else { return false; };
+
+        // King on the edge.
+        if !self.geo.edges.get(k_idx) { return false; }
+
+        // King able to move.
+        let occupied = self.black_pieces | self.white_pieces | self.king_piece;
+        if self.get_orthogonal_neighbors(k_idx).iter().all(|&n| occupied.get(n)) { return false; }
+
+        // Flood fill the interior of the fort.
+        let mut interior = Bitboard::from_index(k_idx);
+        let mut walls = Bitboard::EMPTY;
+        let mut stack = [0usize; MAX_SQUARES];
+        let mut top = 1;
+        stack[0] = k_idx;
+        while top > 0 {
+            top -= 1;
+            for n in self.get_orthogonal_neighbors(stack[top]) {
+                if interior.get(n) { continue; }
+                if self.white_pieces.get(n) { walls.set(n); continue; }
+                // Black can reach the king: no fort.
+                if self.black_pieces.get(n) { return false; }
+                interior.set(n);
+                stack[top] = n;
+                top += 1;
             }
-            return Some('B');
         }
-        if !self.has_legal_move(self.player) {
-            let winner = if self.player == 'B' { 'W' } else { 'B' };
-            return Some(winner);
+
+        // Walls must be uncapturable.
+        // Start from all the white pawns and drop the capturable ones until none is left to drop.
+        let mut safe = self.white_pieces;
+        loop {
+            let mut still_safe = Bitboard::EMPTY;
+            for w in safe.iter() {
+                if self.is_safe_pawn(w, safe, interior) { still_safe.set(w); }
+            }
+            if still_safe == safe { break; }
+            safe = still_safe;
         }
-        if self.is_insufficient_material_draw() { return Some('D'); }
+        walls.iter().all(|w| safe.get(w))
+    }
 
-        None
+    /// A white pawn that black cannot sandwich: on both axes, one side is the edge,
+    /// a pawn of safe or a square of the interior that is not hostile to white.
+    fn is_safe_pawn(&self, w: usize, safe: Bitboard, interior: Bitboard) -> bool {
+        let size = self.geo.size;
+        let is_safe_side = |side: Option<usize>| -> bool {
+            match side {
+                None => true, // Edge of the board.
+                Some(i) => {
+                    let hostile = (self.rules.hostile_corners && self.geo.corners.get(i))
+                                  || (self.rules.empty_throne_hostile_to_white && i == self.geo.throne_idx);
+                    safe.get(i) || (interior.get(i) && !hostile)
+                }
+            }
+        };
+        let (r, c) = (w / size, w % size);
+        let north = if r > 0 { Some(w - size) } else { None };
+        let south = if r < size - 1 { Some(w + size) } else { None };
+        let west = if c > 0 { Some(w - 1) } else { None };
+        let east = if c < size - 1 { Some(w + 1) } else { None };
+        (is_safe_side(north) || is_safe_side(south)) && (is_safe_side(west) || is_safe_side(east))
+    }
+
+    /// The king reached a square he escapes from (a corner, or any edge square with edge escape).
+    #[inline(always)]
+    fn king_escaped(&self) -> bool {
+        self.king_piece.intersects(self.escape_squares())
+    }
+
+    #[inline(always)]
+    fn escape_squares(&self) -> Bitboard {
+        match self.rules.escape {
+            Escape::Corners => self.geo.corners,
+            Escape::Edges => self.geo.edges,
+        }
+    }
+
+    /// Rule 7b: the king and all the white pieces are enclosed by black,
+    /// i.e. no white piece can reach the edge.
+    pub fn is_surrounded(&self) -> bool {
+        if !self.rules.surrounding { return false; }
+        !self.white_can_reach_edge(self.black_pieces, self.white_pieces | self.king_piece)
+    }
+
+    /// Flood fill (on the bitboards) from the white pieces through the squares not occupied by black.
+    /// Returns true as soon as the filled region touches the edge.
+    fn white_can_reach_edge(&self, black: Bitboard, white: Bitboard) -> bool {
+        self.flood_reaches(white, self.geo.board & !black, self.geo.edges)
+    }
+
+    /// Flood fill from start through the free squares.
+    /// Returns true as soon as the filled region touches target.
+    fn flood_reaches(&self, start: Bitboard, free: Bitboard, target: Bitboard) -> bool {
+        let mut region = start;
+        loop {
+            if region.intersects(target) { return true; }
+            let grown = self.geo.dilate(region) & free;
+            if grown == region { return false; }
+            region = grown;
+        }
     }
 
-    /// Simple heuristic for rule 10: declare draw if both sides have very few pieces left.
-    /// Copenhagen: "If it is not possible to end the game, fx. because both sides have too few pieces left, it is a draw."
-    /// This rule is intentionally vague; adjust DRAW_PIECE_THRESHOLD as desired.
+    /// Flood fill from start through the free squares, returning the filled region.
+    fn flood(&self, start: Bitboard, free: Bitboard) -> Bitboard {
+        let mut region = start;
+        loop {
+            let grown = self.geo.dilate(region) & free;
+            if grown == region { return region; }
+            region = grown;
+        }
+    }
+
+    /// Result of the game if the last move repeated a state, according to the repetition rule.
+    #[inline]
+    fn repetition_result(&self) -> Option<GameOutcome> {
+        if !self.repetition { return None; }
+        match self.rules.repetition {
+            RepetitionRule::WhiteLoses => Some(GameOutcome::win(Player::Black, EndReason::Repetition)),
+            RepetitionRule::Draw => Some(GameOutcome::draw(EndReason::Repetition)),
+            RepetitionRule::Allowed => None,
+        }
+    }
+
+    /// Rule 10: "If it is not possible to end the game, fx. because both sides have too few pieces left, it is a draw."
+    /// The game is drawn only when neither side has any way left to win (repetitions aside).
     #[inline]
     fn is_insufficient_material_draw(&self) -> bool {
-        // Count bits
-        let attackers = self.black_pieces.count_ones();
-        let defenders = self.white_pieces.count_ones();
-        
-        // Thresholds
-        attackers <= 2 && defenders <= 1
+        !self.white_can_win() && !self.black_can_win()
+    }
+
+    /// White may still win: the king can walk to an escape square around the black pieces,
+    /// or the white pieces can get to the squares needed to capture a black piece or to block them all (rule 9).
+    fn white_can_win(&self) -> bool {
+        let free = self.geo.board & !self.black_pieces;
+        if self.flood_reaches(self.king_piece, free, self.escape_squares()) { return true; }
+
+        let white = self.white_pieces | self.king_piece;
+        let capturers = if self.rules.king_armed { white } else { self.white_pieces };
+        let reach = self.flood(capturers, free) & !self.geo.restricted;
+        if self.can_sandwich(self.black_pieces, self.hostile_to_black(), reach, capturers.count_ones() as usize) { return true; }
+        if self.rules.shieldwall && white.count_ones() >= 2 && self.can_reach_front(self.black_pieces, reach) { return true; }
+
+        // Every square a black piece could move to has to be taken by a white piece.
+        let targets = self.geo.dilate(self.black_pieces) & !self.black_pieces & !self.geo.restricted;
+        (targets & !self.flood(white, free)).is_empty() && targets.count_ones() <= white.count_ones()
+    }
+
+    /// Black may still win if its pieces can get to the squares needed to capture the king or a white pawn,
+    /// to block all the white pieces (rule 9) or to enclose them (rule 7b).
+    /// The king may move anywhere around the black pieces, so he counts as an empty square.
+    fn black_can_win(&self) -> bool {
+        let attackers = self.black_pieces.count_ones() as usize;
+        if self.rules.surrounding && attackers >= 4 { return true; }
+
+        let reach = self.flood(self.black_pieces, self.geo.board & !self.white_pieces) & !self.geo.restricted;
+        let king_region = self.flood(self.king_piece, self.geo.board & !self.black_pieces) & !self.escape_squares();
+        if self.king_attackers_needed(king_region, reach) <= attackers { return true; }
+
+        if self.can_sandwich(self.white_pieces, self.hostile_to_white(), reach, attackers) { return true; }
+        if self.rules.shieldwall && attackers >= 3 && self.can_reach_front(self.white_pieces, reach) { return true; }
+
+        if self.white_pieces.is_empty() { return self.king_blockers_needed(king_region, reach) <= attackers; }
+        // The pawns are blocked where they stand, the king has no square left to go to.
+        let targets = (self.geo.dilate(self.white_pieces) & !self.geo.restricted | self.geo.dilate(self.king_piece))
+            & !self.white_pieces & !self.king_piece;
+        (targets & !reach).is_empty() && targets.count_ones() as usize <= attackers
+    }
+
+    /// True if one of the victims can be captured where it stands: the squares on both sides of it along a line
+    /// are hostile, or a capturer can get to them, and there are enough capturers for the squares that need one.
+    fn can_sandwich(&self, victims: Bitboard, hostile: Bitboard, reach: Bitboard, capturers: usize) -> bool {
+        let size = self.geo.size;
+        victims.iter().any(|x| {
+            let (r, c) = (x / size, x % size);
+            let horizontal = if c > 0 && c < size - 1 { self.pieces_needed(&[x - 1, x + 1], hostile, reach) } else { None };
+            let vertical = if r > 0 && r < size - 1 { self.pieces_needed(&[x - size, x + size], hostile, reach) } else { None };
+            [horizontal, vertical].into_iter().flatten().any(|needed| (1..=capturers).contains(&needed))
+        })
+    }
+
+    /// True if a piece on the edge has its inner neighbor within reach, the first step of a shieldwall capture (rule 4b).
+    fn can_reach_front(&self, victims: Bitboard, reach: Bitboard) -> bool {
+        let inner = self.geo.board & !self.geo.edges;
+        (victims & self.geo.edges).iter().any(|x| {
+            self.get_orthogonal_neighbors(x).iter().any(|&n| inner.get(n) && reach.get(n))
+        })
+    }
+
+    /// Restricted squares hostile to black pieces.
+    fn hostile_to_black(&self) -> Bitboard {
+        let mut hostile = Bitboard::EMPTY;
+        if self.rules.hostile_corners { hostile |= self.geo.corners; }
+        if self.rules.throne_hostile_to_black { hostile |= self.geo.throne; }
+        hostile
+    }
+
+    /// Restricted squares hostile to white pieces (the throne only when empty, which it is once the king moves away).
+    fn hostile_to_white(&self) -> Bitboard {
+        let mut hostile = Bitboard::EMPTY;
+        if self.rules.hostile_corners { hostile |= self.geo.corners; }
+        if self.rules.empty_throne_hostile_to_white { hostile |= self.geo.throne; }
+        hostile
+    }
+
+    /// Pieces needed on the given squares to make all of them hostile (None if one of them is out of reach).
+    fn pieces_needed(&self, squares: &[usize], hostile: Bitboard, reach: Bitboard) -> Option<usize> {
+        squares.iter().try_fold(0, |needed, &n| {
+            if hostile.get(n) { Some(needed) }
+            else if reach.get(n) { Some(needed + 1) }
+            else { None }
+        })
+    }
+
+    /// Fewest black pieces that can capture the king, on the square of his region where he is easiest to capture.
+    /// Hostile corners and the hostile throne take the place of attackers; the others have to be within reach.
+    fn king_attackers_needed(&self, region: Bitboard, reach: Bitboard) -> usize {
+        let size = self.geo.size;
+        let throne_idx = self.geo.throne_idx;
+        let hostile = self.hostile_to_white();
+        region.iter().filter_map(|k| {
+            let neighbors = self.get_orthogonal_neighbors(k);
+            let four_sides = if neighbors.len() == 4 { self.pieces_needed(&neighbors, hostile, reach) } else { None };
+            let two_sides = match self.rules.king_capture {
+                KingCapture::FourSides => false,
+                KingCapture::Standard => k != throne_idx && !self.geo.is_next_to_throne(k),
+                KingCapture::TwoSides => true,
+            };
+            if !two_sides { return four_sides; }
+            let (r, c) = (k / size, k % size);
+            let horizontal = if c > 0 && c < size - 1 { self.pieces_needed(&[k - 1, k + 1], hostile, reach) } else { None };
+            let vertical = if r > 0 && r < size - 1 { self.pieces_needed(&[k - size, k + size], hostile, reach) } else { None };
+            [four_sides, horizontal, vertical].into_iter().flatten().min()
+        }).min().unwrap_or(usize::MAX)
+    }
+
+    /// Fewest black pieces that can block a lone king on a square of his region: every neighbor has to be occupied,
+    /// and black cannot stand on the restricted squares he may move to.
+    fn king_blockers_needed(&self, region: Bitboard, reach: Bitboard) -> usize {
+        region.iter()
+            .map(|k| self.get_orthogonal_neighbors(k))
+            .filter(|neighbors| neighbors.iter().all(|&n| reach.get(n)))
+            .map(|neighbors| neighbors.len())
+            .min().unwrap_or(usize::MAX)
     }
 
     // ======================================
@@ -775,26 +1534,27 @@ impl GameState {
 
     /// Fast check if a move results in a capture. 
     /// Used for Hard Playouts in MCTS.
-    pub fn is_capture_move(&self, coords: &[usize; 4]) -> bool {
-        let (sr, sc, er, ec) = (coords[0], coords[1], coords[2], coords[3]);
-        let src_mask = 1 << Self::idx(sr, sc);
+    pub fn is_capture_move(&self, m: &Move) -> bool {
+        let (sr, sc, er, ec) = m.coords(self.geo.size);
         
         // Identify who is moving
-        let mover_is_black = (self.black_pieces & src_mask) != 0;
+        let src = self.idx(sr, sc);
+        let mover_is_black = self.black_pieces.get(src);
+
+        // An unarmed king does not capture.
+        if self.king_piece.get(src) && !self.rules.king_armed { return false; }
         
         // We need the state AFTER the move to check anvil conditions strictly,
         // but for a heuristic, we can approximate using the current state 
         // assuming the 'dst' square becomes occupied by the mover.
-        let dst_idx = Self::idx(er, ec);
+        let dst_idx = self.idx(er, ec);
         
         let neighbors = self.get_orthogonal_neighbors(dst_idx);
         for &victim_idx in &neighbors {
-            let v_mask = 1 << victim_idx;
-            
             // 1. Check if neighbor is an enemy
-            let is_victim_black = (self.black_pieces & v_mask) != 0;
-            let is_victim_white = (self.white_pieces & v_mask) != 0;
-            let is_victim_king = (self.king_piece & v_mask) != 0;
+            let is_victim_black = self.black_pieces.get(victim_idx);
+            let is_victim_white = self.white_pieces.get(victim_idx);
+            let is_victim_king = self.king_piece.get(victim_idx);
 
             if !is_victim_black && !is_victim_white && !is_victim_king { continue; }
 
@@ -808,7 +1568,7 @@ impl GameState {
             // but usually standard capture logic covers 90% of cases).
             if is_victim_king {
                  // Reuse your existing logic or a simplified version
-                 if self.check_king_captured_sim(self.black_pieces | (1<<dst_idx), self.king_piece) {
+                 if self.check_king_captured_sim(self.black_pieces | Bitboard::from_index(dst_idx), self.king_piece) {
                      return true;
                  }
                  continue;
@@ -828,6 +1588,18 @@ impl GameState {
                 }
             }
         }
+
+        // 4. Check Shieldwall Capture (needs the exact boards after the move: the mover may leave the front of the row).
+        if self.rules.shieldwall {
+            let move_mask = Bitboard::from_index(src) | Bitboard::from_index(dst_idx);
+            let (mut b, mut w, mut k) = (self.black_pieces, self.white_pieces, self.king_piece);
+            if mover_is_black { b ^= move_mask; }
+            else if self.white_pieces.get(src) { w ^= move_mask; }
+            else { k ^= move_mask; }
+            if !self.shieldwall_captures(dst_idx, mover_is_black, b, w, k).is_empty() {
+                return true;
+            }
+        }
         false
     }
 
@@ -836,124 +1608,43 @@ impl GameState {
     // ======================================
 
     /// Returns true if Black can capture the King immediately.
-    pub fn heuristic_capture_king(&self) -> (bool, Option<[usize; 4]>) {
+    pub fn heuristic_capture_king(&self) -> (bool, Option<Move>) {
         // Black must be the player moving.
-        if self.player != 'B' { return (false, None); }
+        if self.player != Player::Black { return (false, None); }
         
         // Find King.
-        let k_idx = self.king_piece.trailing_zeros() as usize;
-        if k_idx >= 64 { return (false, None); } // Should not happen.
+        let Some(k_idx) = self.king_piece.first() else { return (false, None); }; // Should not happen.
 
         // Get neighbors.
         let neighbors = self.get_orthogonal_neighbors(k_idx);
         let occupied = self.black_pieces | self.white_pieces | self.king_piece;
 
-        // === CASE 1: King on Throne (24) ===
-        // Needs 4 attackers. We need 3 present + 1 reachable empty spot.
-        if k_idx == 24 {
-            let mut black_count = 0;
-            let mut empty_spot = None;
-
-            for &n in &neighbors {
-                if (self.black_pieces & (1 << n)) != 0 {
-                    black_count += 1;
-                } else if (occupied & (1 << n)) == 0 {
-                    empty_spot = Some(n);
-                }
-            }
-
-            if black_count == 3
-            && let Some(target) = empty_spot
-            && let Some(mv) = self.get_black_move_to(target) {
-                return (true, Some(mv));
-            }
-            return (false, None);
-        }
-
-        // === CASE 2: King Next to Throne (23, 25, 17, 31) ===
-        // Needs 3 attackers + Throne (which acts as the 4th anvil).
-        // We need 2 present (excluding throne) + 1 reachable empty spot.
-        let is_next_throne = k_idx == 23 || k_idx == 25 || k_idx == 17 || k_idx == 31;
-        if is_next_throne {
-            let mut black_count = 0;
-            let mut empty_spot = None;
-
-            for &n in &neighbors {
-                if n == 24 { continue; } // Skip Throne (it's the anvil, not an attacker)
-                
-                if (self.black_pieces & (1 << n)) != 0 {
-                    black_count += 1;
-                } else if (occupied & (1 << n)) == 0 {
-                    empty_spot = Some(n);
+        // The king can only be captured by a black piece moving next to him:
+        // try every empty neighbor reachable by black, and check the capture with the current rules.
+        for &n in &neighbors {
+            if occupied.get(n) { continue; }
+            if let Some(mv) = self.get_black_move_to(n) {
+                let (sr, sc, _, _) = mv.coords(self.geo.size);
+                let src = self.idx(sr, sc);
+                let next_black = self.black_pieces ^ Bitboard::from_index(src) ^ Bitboard::from_index(n);
+                if self.check_king_captured_sim(next_black, self.king_piece) {
+                    return (true, Some(mv));
                 }
             }
-
-            if black_count == 2
-            && let Some(target) = empty_spot
-            && let Some(mv) = self.get_black_move_to(target) {
-                return (true, Some(mv));
-            }
-            return (false, None);
-        }
-
-        // === CASE 3: Standard Capture (Sandwich) ===
-        // Check Horizontal Pair
-        let r = k_idx / 7;
-        let c = k_idx % 7;
-
-        // Check Horizontal (West/East)
-        if c > 0 && c < 6 {
-            let w = k_idx - 1;
-            let e = k_idx + 1;
-            // Check West as Killer, East as Anvil
-            if self.is_hostile_anvil_for_heuristic(e) && (occupied & (1 << w)) == 0
-            && let Some(mv) = self.get_black_move_to(w) {
-                return (true, Some(mv));
-            }
-            // Check East as Killer, West as Anvil
-            if self.is_hostile_anvil_for_heuristic(w) && (occupied & (1 << e)) == 0
-            && let Some(mv) = self.get_black_move_to(e) {
-                return (true, Some(mv));
-            }
-        }
-
-        // Check Vertical (North/South)
-        if r > 0 && r < 6 {
-            let n = k_idx - 7;
-            let s = k_idx + 7;
-            // Check North as Killer, South as Anvil
-            if self.is_hostile_anvil_for_heuristic(s) && (occupied & (1 << n)) == 0
-            && let Some(mv) = self.get_black_move_to(n) {
-                return (true, Some(mv));
-            }
-            // Check South as Killer, North as Anvil
-            if self.is_hostile_anvil_for_heuristic(n) && (occupied & (1 << s)) == 0
-            && let Some(mv) = self.get_black_move_to(s) {
-                return (true, Some(mv));
-            }
         }
 
         (false, None)
     }
 
-    /// Helper: Checks if a square acts as an Anvil for Black (Black piece, Corner, or Throne).
-    #[inline]
-    fn is_hostile_anvil_for_heuristic(&self, idx: usize) -> bool {
-        let mask = 1 << idx;
-        if (self.black_pieces & mask) != 0 { return true; }
-        if (CORNERS & mask) != 0 { return true; }
-        if (THRONE & mask) != 0 { return true; } // Throne is hostile to King if empty (logic derived from game rules)
-        false
-    }
-
     /// Helper: Checks if ANY Black piece can legally move to `target_idx`.
     /// Returns the move [sr, sc, er, ec] if found.
-    fn get_black_move_to(&self, target_idx: usize) -> Option<[usize; 4]> {
+    fn get_black_move_to(&self, target_idx: usize) -> Option<Move> {
         // Black cannot capture by landing ON a restricted square (Throne/Corners).
-        if (RESTRICTED & (1 << target_idx)) != 0 { return None; }
+        if self.geo.restricted.get(target_idx) { return None; }
 
-        let r = target_idx / 7;
-        let c = target_idx % 7;
+        let n = self.geo.size;
+        let r = target_idx / n;
+        let c = target_idx % n;
         let occupied = self.black_pieces | self.white_pieces | self.king_piece;
 
         // Scan OUTWARDS from target to find a source piece.
@@ -962,39 +1653,88 @@ impl GameState {
 
         // UP
         for rr in (0..r).rev() {
-            let curr = Self::idx(rr, c);
-            if (occupied & (1 << curr)) != 0 {
-                if (self.black_pieces & (1 << curr)) != 0 { return Some([rr, c, r, c]); }
+            let curr = self.idx(rr, c);
+            if occupied.get(curr) {
+                if self.black_pieces.get(curr) { return Some(Move::new(rr, c, r, c, self.geo.size)); }
                 break; // Blocked by White/King
             }
         }
         // DOWN
-        for rr in r+1..7 {
-            let curr = Self::idx(rr, c);
-            if (occupied & (1 << curr)) != 0 {
-                if (self.black_pieces & (1 << curr)) != 0 { return Some([rr, c, r, c]); }
+        for rr in r+1..n {
+            let curr = self.idx(rr, c);
+            if occupied.get(curr) {
+                if self.black_pieces.get(curr) { return Some(Move::new(rr, c, r, c, self.geo.size)); }
                 break;
             }
         }
         // LEFT
         for cc in (0..c).rev() {
-            let curr = Self::idx(r, cc);
-            if (occupied & (1 << curr)) != 0 {
-                if (self.black_pieces & (1 << curr)) != 0 { return Some([r, cc, r, c]); }
+            let curr = self.idx(r, cc);
+            if occupied.get(curr) {
+                if self.black_pieces.get(curr) { return Some(Move::new(r, cc, r, c, self.geo.size)); }
                 break;
             }
         }
         // RIGHT
-        for cc in c+1..7 {
-            let curr = Self::idx(r, cc);
-            if (occupied & (1 << curr)) != 0 {
-                if (self.black_pieces & (1 << curr)) != 0 { return Some([r, cc, r, c]); }
+        for cc in c+1..n {
+            let curr = self.idx(r, cc);
+            if occupied.get(curr) {
+                if self.black_pieces.get(curr) { return Some(Move::new(r, cc, r, c, self.geo.size)); }
                 break;
             }
         }
         None
     }
 
+    /// Returns true if Black can enclose all the white pieces with one move (rule 7b).
+    /// Only the empty squares reachable by white with at least two black neighbors
+    /// (orthogonal or diagonal) are tried as the square closing the ring.
+    pub fn heuristic_surround(&self) -> (bool, Option<Move>) {
+        // Black must be the player moving.
+        if self.player != Player::Black || !self.rules.surrounding { return (false, None); }
+
+        // A white piece on the edge cannot be enclosed.
+        let white = self.white_pieces | self.king_piece;
+        if white.intersects(self.geo.edges) { return (false, None); }
+
+        // Region reachable by white.
+        let free = self.geo.board & !self.black_pieces;
+        let mut region = white;
+        loop {
+            let grown = self.geo.dilate(region) & free;
+            if grown == region { break; }
+            region = grown;
+        }
+
+        let n = self.geo.size as isize;
+        for x in (region & !white).iter() {
+            let (r, c) = ((x as isize) / n, (x as isize) % n);
+            let mut black_neighbors = 0;
+            for dr in -1..=1 {
+                for dc in -1..=1 {
+                    let (rr, cc) = (r + dr, c + dc);
+                    if (0..n).contains(&rr) && (0..n).contains(&cc)
+                    && self.black_pieces.get((rr * n + cc) as usize) {
+                        black_neighbors += 1;
+                    }
+                }
+            }
+            if black_neighbors < 2 { continue; }
+
+            // Captures can only remove white pieces, so we can ignore them.
+            if let Some(mv) = self.get_black_move_to(x) {
+                let (sr, sc, _, _) = mv.coords(self.geo.size);
+                let src = self.idx(sr, sc);
+                let next_black = self.black_pieces ^ Bitboard::from_index(src) ^ Bitboard::from_index(x);
+                if !self.white_can_reach_edge(next_black, white) {
+                    return (true, Some(mv));
+                }
+            }
+        }
+
+        (false, None)
+    }
+
     // =======================================
     //            HEURISTICS WHITE            
     // =======================================
@@ -1002,9 +1742,9 @@ impl GameState {
     /// Returns true if from the current state white can win, whatever black does.
     pub fn heuristic_wins_w(&self) -> bool {
         // White must be the player moving.
-        if self.player != 'W' { return false; }
+        if self.player != Player::White { return false; }
 
-        // 1. King to corner.
+        // 1. King to corner (or edge).
         if self.heuristic_king_to_corner().0 { return true; }
 
         // 2. King to empty edge.
@@ -1013,168 +1753,275 @@ impl GameState {
         false
     }
 
-    /// 1. The King has a clear path to a corner.
-    pub fn heuristic_king_to_corner(&self) -> (bool, Option<[usize; 4]>) {
-        let k_idx = self.king_piece.trailing_zeros() as usize;
-        if k_idx >= 64 { return (false, None); }
+    /// 1. The King has a clear path to a corner (to any edge square with edge escape).
+    pub fn heuristic_king_to_corner(&self) -> (bool, Option<Move>) {
+        let Some(k_idx) = self.king_piece.first() else { return (false, None); };
 
-        let r = k_idx / 7;
-        let c = k_idx % 7;
+        let n = self.geo.size;
+        let last = n - 1;
+        let r = k_idx / n;
+        let c = k_idx % n;
         let occupied = self.black_pieces | self.white_pieces | self.king_piece;
 
         // Helper to check linear path (exclusive of start, inclusive of end)
         let check_path = |start_idx: usize, step: isize, count: usize| -> bool {
             let mut curr = start_idx as isize + step;
             for _ in 0..count {
-                if (occupied & (1u64 << curr)) != 0 { return false; }
+                if occupied.get(curr as usize) { return false; }
                 curr += step;
             }
             true
         };
+        let row_step = n as isize;
+
+        if self.rules.escape == Escape::Edges {
+            if r > 0 && check_path(k_idx, -row_step, r) { return (true, Some(Move::new(r, c, 0, c, self.geo.size))); }
+            if r < last && check_path(k_idx, row_step, last - r) { return (true, Some(Move::new(r, c, last, c, self.geo.size))); }
+            if c > 0 && check_path(k_idx, -1, c) { return (true, Some(Move::new(r, c, r, 0, self.geo.size))); }
+            if c < last && check_path(k_idx, 1, last - c) { return (true, Some(Move::new(r, c, r, last, self.geo.size))); }
+            return (false, None);
+        }
 
         // 1. Top-Left (0,0)
         // Check West (if on row 0)
         if r == 0 && c > 0
-        && check_path(k_idx, -1, c) { return (true, Some([r, c, 0, 0])); }
+        && check_path(k_idx, -1, c) { return (true, Some(Move::new(r, c, 0, 0, self.geo.size))); }
         // Check North (if on col 0)
         if c == 0 && r > 0
-        && check_path(k_idx, -7, r) { return (true, Some([r, c, 0, 0])); }
+        && check_path(k_idx, -row_step, r) { return (true, Some(Move::new(r, c, 0, 0, self.geo.size))); }
 
-        // 2. Top-Right (0,6)
+        // 2. Top-Right (0,last)
         // Check East (if on row 0)
-        if r == 0 && c < 6
-        && check_path(k_idx, 1, 6 - c) { return (true, Some([r, c, 0, 6])); }
-        // Check North (if on col 6)
-        if c == 6 && r > 0
-        && check_path(k_idx, -7, r) { return (true, Some([r, c, 0, 6])); }
-
-        // 3. Bottom-Left (6,0)
-        // Check West (if on row 6)
-        if r == 6 && c > 0
-        && check_path(k_idx, -1, c) { return (true, Some([r, c, 6, 0])); }
+        if r == 0 && c < last
+        && check_path(k_idx, 1, last - c) { return (true, Some(Move::new(r, c, 0, last, self.geo.size))); }
+        // Check North (if on last col)
+        if c == last && r > 0
+        && check_path(k_idx, -row_step, r) { return (true, Some(Move::new(r, c, 0, last, self.geo.size))); }
+
+        // 3. Bottom-Left (last,0)
+        // Check West (if on last row)
+        if r == last && c > 0
+        && check_path(k_idx, -1, c) { return (true, Some(Move::new(r, c, last, 0, self.geo.size))); }
         // Check South (if on col 0)
-        if c == 0 && r < 6
-        && check_path(k_idx, 7, 6 - r) { return (true, Some([r, c, 6, 0])); }
+        if c == 0 && r < last
+        && check_path(k_idx, row_step, last - r) { return (true, Some(Move::new(r, c, last, 0, self.geo.size))); }
 
-        // 4. Bottom-Right (6,6)
-        // Check East (if on row 6)
-        if r == 6 && c < 6
-        && check_path(k_idx, 1, 6 - c) { return (true, Some([r, c, 6, 6])); }
-        // Check South (if on col 6)
-        if c == 6 && r < 6
-        && check_path(k_idx, 7, 6 - r) { return (true, Some([r, c, 6, 6])); }
+        // 4. Bottom-Right (last,last)
+        // Check East (if on last row)
+        if r == last && c < last
+        && check_path(k_idx, 1, last - c) { return (true, Some(Move::new(r, c, last, last, self.geo.size))); }
+        // Check South (if on last col)
+        if c == last && r < last
+        && check_path(k_idx, row_step, last - r) { return (true, Some(Move::new(r, c, last, last, self.geo.size))); }
 
         (false, None)
     }
 
     /// 2. The King has a clear path to an empty edge (cannot be protected by black anymore).
-    pub fn heuristic_king_empty_edge(&self) -> (bool, Option<[usize; 4]>) {
-        let k_idx = self.king_piece.trailing_zeros() as usize;
-        if k_idx >= 64 { return (false, None); }
-
-        let r = k_idx / 7;
-        let c = k_idx % 7;
+    ///
+    /// With edge escape this is already a win, found by heuristic_king_to_corner.
+    pub fn heuristic_king_empty_edge(&self) -> (bool, Option<Move>) {
+        if self.rules.escape == Escape::Edges { return (false, None); }
+        let Some(k_idx) = self.king_piece.first() else { return (false, None); };
+
+        let n = self.geo.size;
+        let last = n - 1;
+        let r = k_idx / n;
+        let c = k_idx % n;
         let occupied = self.black_pieces | self.white_pieces | self.king_piece;
 
         let check_path = |start_idx: usize, step: isize, count: usize| -> bool {
             let mut curr = start_idx as isize + step;
             for _ in 0..count {
-                if (occupied & (1u64 << curr)) != 0 { return false; }
+                if occupied.get(curr as usize) { return false; }
                 curr += step;
             }
             true
         };
+        let row_step = n as isize;
 
         // 1. Top Edge (Row 0) -> Move to (0, c)
-        if (occupied & ROW_0_MASK) == 0
-        && check_path(k_idx, -7, r) { 
-            return (true, Some([r, c, 0, c])); 
+        if !occupied.intersects(self.geo.row_first)
+        && check_path(k_idx, -row_step, r) { 
+            return (true, Some(Move::new(r, c, 0, c, self.geo.size))); 
         }
 
-        // 2. Bottom Edge (Row 6) -> Move to (6, c)
-        if (occupied & ROW_6_MASK) == 0
-        && check_path(k_idx, 7, 6 - r) { 
-            return (true, Some([r, c, 6, c])); 
+        // 2. Bottom Edge (last Row) -> Move to (last, c)
+        if !occupied.intersects(self.geo.row_last)
+        && check_path(k_idx, row_step, last - r) { 
+            return (true, Some(Move::new(r, c, last, c, self.geo.size))); 
         }
 
         // 3. Left Edge (Col 0) -> Move to (r, 0)
-        if (occupied & COL_0_MASK) == 0
+        if !occupied.intersects(self.geo.col_first)
         && check_path(k_idx, -1, c) { 
-            return (true, Some([r, c, r, 0])); 
+            return (true, Some(Move::new(r, c, r, 0, self.geo.size))); 
         }
 
-        // 4. Right Edge (Col 6) -> Move to (r, 6)
-        if (occupied & COL_6_MASK) == 0
-        && check_path(k_idx, 1, 6 - c) { 
-            return (true, Some([r, c, r, 6])); 
+        // 4. Right Edge (last Col) -> Move to (r, last)
+        if !occupied.intersects(self.geo.col_last)
+        && check_path(k_idx, 1, last - c) { 
+            return (true, Some(Move::new(r, c, r, last, self.geo.size))); 
         }
 
         (false, None)
     }
 
+    // =================================
+    //            SYMMETRIES
+    // =================================
+
+    /// Hash shared by the eight rotations and reflections of the position: the smallest of their hashes.
+    /// Used by MCTS (with canonical_hash) so that symmetric positions share their statistics.
+    pub fn canonical_hash(&self, z_table: &Zobrist) -> u64 {
+        Self::canonical_hash_of(&self.symmetric_keys(z_table), self.player, z_table)
+    }
+
+    /// Zobrist hashes of the boards (without the side to move) under each symmetry of Symmetry::ALL.
+    pub fn symmetric_keys(&self, z_table: &Zobrist) -> [u64; 8] {
+        let pieces = (self.black_pieces, self.white_pieces, self.king_piece);
+        Symmetry::ALL.map(|sym| Self::hash_boards(self.geo.size, pieces, sym, z_table))
+    }
+
+    /// Canonical hash of the position after the move, without applying it (see next_hash).
+    /// keys are the symmetric_keys of the current position, computed once for all its moves:
+    /// only the squares the move changes are hashed.
+    pub fn canonical_next_hash(&self, m: &Move, keys: &[u64; 8], z_table: &Zobrist) -> u64 {
+        let n = self.geo.size;
+        let (black, white, king) = self.predict_next_boards(m);
+        let changes = [self.black_pieces ^ black, self.white_pieces ^ white, self.king_piece ^ king];
+        let mut keys = *keys;
+        for (p_idx, changed) in changes.into_iter().enumerate() {
+            for i in changed.iter() {
+                for (key, sym) in keys.iter_mut().zip(Symmetry::ALL) {
+                    let (r, c) = sym.apply(i / n, i % n, n);
+                    *key ^= z_table.table[r][c][p_idx];
+                }
+            }
+        }
+        Self::canonical_hash_of(&keys, self.player.opponent(), z_table)
+    }
+
+    fn canonical_hash_of(keys: &[u64; 8], player: Player, z_table: &Zobrist) -> u64 {
+        let key = *keys.iter().min().unwrap();
+        if player == Player::Black { key ^ z_table.black_to_move } else { key }
+    }
+
+    // =================================
+    //              PERFT
+    // =================================
+
+    /// Count the positions reached after depth plies, to validate move generation.
+    /// Finished games are not expanded. no_repetition is passed to get_legal_moves at every node.
+    pub fn perft(&self, depth: usize, no_repetition: bool, z_table: &Zobrist) -> u64 {
+        let mut state = self.clone();
+        state.perft_in_place(depth, no_repetition, z_table)
+    }
+
+    /// Same as perft, but prints the count for each root move.
+    pub fn perft_divide<W: Write>(&self, depth: usize, no_repetition: bool, z_table: &Zobrist, writer: &mut W) -> u64 {
+        // Nothing to divide: the position itself is the only node, as in perft.
+        if depth == 0 {
+            writeln!(writer, "Nodes: 1").expect("could not write to output");
+            return 1;
+        }
+        let mut state = self.clone();
+        let mut moves = MoveList::new();
+        if state.check_game_over(z_table).is_none() {
+            state.get_legal_moves(&mut moves, no_repetition, z_table);
+        }
+
+        let mut total = 0;
+        let mut sink = io::sink();
+        for m in &moves {
+            let undo = state.make_move(m, z_table, true, &mut sink);
+            let count = state.perft_in_place(depth - 1, no_repetition, z_table);
+            state.unmake_move(&undo);
+            writeln!(writer, "{}: {}", m, count).expect("could not write to output");
+            total += count;
+        }
+        writeln!(writer, "\nMoves: {}", moves.len()).expect("could not write to output");
+        writeln!(writer, "Nodes: {}", total).expect("could not write to output");
+        total
+    }
+
+    fn perft_in_place(&mut self, depth: usize, no_repetition: bool, z_table: &Zobrist) -> u64 {
+        if depth == 0 { return 1; }
+        if self.check_game_over(z_table).is_some() { return 0; }
+
+        let mut moves = MoveList::new();
+        self.get_legal_moves(&mut moves, no_repetition, z_table);
+        // Bulk counting.
+        if depth == 1 { return moves.len() as u64; }
+
+        let mut nodes = 0;
+        let mut sink = io::sink();
+        for m in &moves {
+            let undo = self.make_move(m, z_table, true, &mut sink);
+            nodes += self.perft_in_place(depth - 1, no_repetition, z_table);
+            self.unmake_move(&undo);
+        }
+        nodes
+    }
+
     // =================================
     //            HUMAN INPUT
     // =================================
 
     /// Gets a move from CLI.
-    /// If valid then moves the piece.
-    pub fn human_move<W: Write>(&mut self, z_table: &Zobrist, writer: &mut W) {
+    /// If valid then moves the piece and returns its undo record.
+    /// Returns None if the player asked for a takeback ("undo").
+    pub fn human_move<W: Write>(&mut self, z_table: &Zobrist, writer: &mut W) -> Option<Undo> {
         loop {
             writeln!(writer, "\nCurrent Player: {}", self.player).expect("could not write to output");
-            write!(writer, "Enter move: ").expect("could not write to output");
+            write!(writer, "Enter move (e.g. a4-a2, or undo): ").expect("could not write to output");
             writer.flush().expect("Flush failed");
             // Get input string.
             io::stdout().flush().unwrap();
             let mut input = String::new();
             io::stdin().read_line(&mut input).expect("Failed to read line");
 
-            // Create array of length 4
-            let res: Result<[usize; 4], _> = input
-                .split_whitespace()
-                .filter_map(|s| s.parse().ok())
-                .collect::<Vec<usize>>()
-                .try_into();
+            if input.trim() == "undo" { return None; }
 
-            match res {
-                Ok(coords) => {
+            // Parse the move in tafl notation.
+            match Move::parse(&input, self.geo.size) {
+                Ok(m) => {
                     // Check if the move is valid and do it.
-                    if self.is_legal_move_human(&coords) {
-                        self.move_piece(&coords, z_table, false, writer);
-                        return;
+                    if self.is_legal_move_human(&m) {
+                        return Some(self.make_move(&m, z_table, false, writer));
                     } else {
                         continue;
                     }
                 }
-                Err(_) => {
-                    writeln!(writer, "Invalid input. Try again.\n").expect("could not write to output");
+                Err(e) => {
+                    writeln!(writer, "Invalid input ({}). Try again.\n", e).expect("could not write to output");
                     continue;
                 }
             }
         }
     }
 
-    /// Check if the given move (coords) is legal.
+    /// Check if the given move is legal.
     /// Used only for user moves.
     #[inline]
-    fn is_legal_move_human(&self, coords: &[usize; 4]) -> bool {
-        let (sr, sc, er, ec) = (coords[0], coords[1], coords[2], coords[3]);
-
-        if sr > 6 || sc > 6 || er > 6 || ec > 6 { return false; }
+    fn is_legal_move_human(&self, m: &Move) -> bool {
+        if !m.fits(self.geo.size) { return false; }
+        let (sr, sc, er, ec) = m.coords(self.geo.size);
 
         // If start == end
         if sr == er && sc == ec { return false; }
 
-        let src = Self::idx(sr, sc);
-        let dst = Self::idx(er, ec);
+        let src = self.idx(sr, sc);
+        let dst = self.idx(er, ec);
 
         // Check piece ownership.
-        let is_mine = if self.player == 'B' { (self.black_pieces & (1<<src)) != 0 }
-                      else { ((self.white_pieces | self.king_piece) & (1<<src)) != 0 };
+        let is_mine = if self.player == Player::Black { self.black_pieces.get(src) }
+                      else { (self.white_pieces | self.king_piece).get(src) };
         if !is_mine { return false; }
 
         // Check destination empty.
         let occupied = self.black_pieces | self.white_pieces | self.king_piece;
-        if (occupied & (1<<dst)) != 0 { return false; }
+        if occupied.get(dst) { return false; }
 
         // Check for straight-line movement.
         if sr != er && sc != ec { return false; } // Not orthogonal
@@ -1187,8 +2034,8 @@ impl GameState {
         let mut curr_r = sr as isize + step_r;
         let mut curr_c = sc as isize + step_c;
         while curr_r != er as isize || curr_c != ec as isize {
-             let idx = Self::idx(curr_r as usize, curr_c as usize);
-             if (occupied & (1<<idx)) != 0 { return false; } // Path blocked
+             let idx = self.idx(curr_r as usize, curr_c as usize);
+             if occupied.get(idx) { return false; } // Path blocked
              curr_r += step_r;
              curr_c += step_c;
         }
@@ -1200,3 +2047,522 @@ impl GameState {
         true
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+    use crate::rules::Preset;
+
+    fn position(fen: &str, rules: RuleSet) -> GameState {
+        GameState::from_fen(fen, rules, &Zobrist::new(1)).expect("invalid test position")
+    }
+
+    /// Play the move, checking that the capture paths which predict it agree with make_move.
+    fn play(state: &mut GameState, m: &str, z_table: &Zobrist) -> Undo {
+        let m = Move::parse(m, state.geo.size).unwrap();
+        let next_hash = state.next_hash(&m, z_table);
+        let next_boards = state.predict_next_boards(&m);
+        let predicted_capture = state.is_capture_move(&m);
+        let undo = state.make_move(&m, z_table, true, &mut io::sink());
+        assert_eq!(state.hash, next_hash, "{}", state.to_fen());
+        assert_eq!(next_boards, (state.black_pieces, state.white_pieces, state.king_piece), "{}", state.to_fen());
+        assert_eq!(predicted_capture, undo.is_capture(), "{}", state.to_fen());
+        undo
+    }
+
+    #[test]
+    fn rules_switch_captures() {
+        let z_table = Zobrist::new(1);
+        // The king takes c3 against b3 only when armed.
+        let fen = "3B3/7/7/7/1WB4/3K3/7 w 0";
+        let mut armed = position(fen, RuleSet::default());
+        play(&mut armed, "d2-d3", &z_table);
+        assert_eq!(armed.black_pieces.count_ones(), 1);
+        let mut unarmed = position(fen, RuleSet { king_armed: false, ..RuleSet::default() });
+        play(&mut unarmed, "d2-d3", &z_table);
+        assert_eq!(unarmed.black_pieces.count_ones(), 2);
+
+        // The corners take part in captures only when hostile.
+        let fen = "1B5/7/2W4/3K3/7/7/3B3 w 0";
+        let mut hostile = position(fen, RuleSet::default());
+        play(&mut hostile, "c5-c7", &z_table);
+        assert_eq!(hostile.black_pieces.count_ones(), 1);
+        let mut neutral = position(fen, RuleSet { hostile_corners: false, ..RuleSet::default() });
+        play(&mut neutral, "c5-c7", &z_table);
+        assert_eq!(neutral.black_pieces.count_ones(), 2);
+    }
+
+    #[test]
+    fn shieldwall_capture() {
+        let z_table = Zobrist::new(1);
+        let mut state = position("11/11/11/11/11/5K5/11/11/5B5/3BB6/2BWW6 b 0", Preset::Copenhagen.rules());
+        play(&mut state, "f3-f1", &z_table);
+        assert!(state.white_pieces.is_empty());
+
+        // A gap in the front row saves the wall.
+        let mut state = position("11/11/11/11/11/5K5/11/11/5B5/3B7/2BWW6 b 0", Preset::Copenhagen.rules());
+        play(&mut state, "f3-f1", &z_table);
+        assert_eq!(state.white_pieces.count_ones(), 2);
+
+        // The king may be part of the wall, but is not captured.
+        let mut state = position("11/11/11/11/11/11/11/11/5B5/3BB6/2BKW6 b 0", Preset::Copenhagen.rules());
+        play(&mut state, "f3-f1", &z_table);
+        assert!(state.white_pieces.is_empty());
+        assert_eq!(state.king_piece.count_ones(), 1);
+
+        // A hostile corner brackets the wall like a black piece.
+        let mut state = position("11/11/11/11/11/5K5/11/11/3B7/1BB8/1WW8 b 0", Preset::Copenhagen.rules());
+        play(&mut state, "d3-d1", &z_table);
+        assert!(state.white_pieces.is_empty());
+
+        // Without rule 4b, nothing is captured.
+        let mut state = position("11/11/11/11/11/5K5/11/11/5B5/3BB6/2BWW6 b 0", RuleSet::default());
+        play(&mut state, "f3-f1", &z_table);
+        assert_eq!(state.white_pieces.count_ones(), 2);
+    }
+
+    #[test]
+    fn exit_fort_leaning_on_outer_pawns() {
+        let z_table = Zobrist::new(1);
+        // The pawns of the bottom row flank each other: none of them can be captured.
+        let fort = position("3BWKWB3/4W1W4/4WWW4/11/11/11/11/11/11/11/11 b 0", Preset::Copenhagen.rules());
+        assert!(fort.is_exit_fort());
+        assert_eq!(fort.check_game_over(&z_table), Some(GameOutcome::win(Player::White, EndReason::ExitFort)));
+    }
+
+    #[test]
+    fn exit_fort_with_capturable_wall() {
+        let z_table = Zobrist::new(1);
+        // The single pawn closing the fort can be sandwiched along its row.
+        let open = position("3BWKWB3/4W1W4/5W5/11/11/11/11/11/11/11/11 b 0", Preset::Copenhagen.rules());
+        assert!(!open.is_exit_fort());
+        assert_eq!(open.check_game_over(&z_table), None);
+    }
+
+    #[test]
+    fn surrounding() {
+        let z_table = Zobrist::new(1);
+        let enclosed = "11/11/5B5/4B1B4/4BWB4/4BKB4/5B5/11/11/11/11 w 0";
+        assert_eq!(position(enclosed, Preset::Copenhagen.rules()).check_game_over(&z_table), Some(GameOutcome::win(Player::Black, EndReason::Surrounded)));
+        assert_eq!(position(enclosed, RuleSet::default()).check_game_over(&z_table), None);
+
+        // The pawn on f7 can still slide to the top edge.
+        let open = "11/11/11/4B1B4/4BWB4/4BKB4/5B5/11/11/11/11 w 0";
+        assert_eq!(position(open, Preset::Copenhagen.rules()).check_game_over(&z_table), None);
+
+        // Every white piece has to be enclosed, not only the king.
+        let free_pawn = "1W9/11/5B5/4B1B4/4BWB4/4BKB4/5B5/11/11/11/11 w 0";
+        assert_eq!(position(free_pawn, Preset::Copenhagen.rules()).check_game_over(&z_table), None);
+    }
+
+    #[test]
+    fn preset_start_positions() {
+        let z_table = Zobrist::new(1);
+        // (preset, board size, attackers, defenders besides the king)
+        for (preset, size, black, white) in [
+            (Preset::Brandubh, 7, 8, 4),
+            (Preset::ArdRi, 7, 16, 8),
+            (Preset::Tablut, 9, 16, 8),
+            (Preset::Copenhagen, 11, 24, 12),
+        ] {
+            let start = GameState::from_preset(preset, &z_table);
+            assert_eq!(start.geo.size, size, "{:?}", preset);
+            assert_eq!(start.black_pieces.count_ones(), black, "{:?}", preset);
+            assert_eq!(start.white_pieces.count_ones(), white, "{:?}", preset);
+            assert_eq!(start.king_piece, start.geo.throne, "{:?}", preset);
+            assert_eq!(start.rules, preset.rules());
+            assert_eq!(start.player, Player::Black);
+            assert_eq!(start.check_game_over(&z_table), None);
+        }
+    }
+
+    #[test]
+    fn edge_escape() {
+        let z_table = Zobrist::new(1);
+        let tablut = Preset::Tablut.rules();
+        let on_edge = "9/9/9/9/K4B3/9/9/9/3B5 b 0";
+        assert_eq!(position(on_edge, tablut).check_game_over(&z_table), Some(GameOutcome::win(Player::White, EndReason::KingEscaped)));
+        assert_eq!(position(on_edge, RuleSet::default()).check_game_over(&z_table), None);
+
+        // The corners are ordinary squares.
+        assert!(GameState::from_fen("B8/9/9/9/4K4/9/9/9/9 w 0", tablut, &Zobrist::new(1)).is_ok());
+        assert!(GameState::from_fen("B8/9/9/9/4K4/9/9/9/9 w 0", RuleSet::default(), &Zobrist::new(1)).is_err());
+
+        // The white heuristics look for any free path to the edge.
+        let free_path = position("9/9/2B6/9/2K2B3/2B6/9/9/9 w 0", tablut);
+        assert_eq!(free_path.heuristic_king_to_corner(), (true, Some(Move::parse("c5-a5", 9).unwrap())));
+        assert!(free_path.heuristic_wins_w());
+        assert_eq!(free_path.heuristic_king_empty_edge(), (false, None));
+    }
+
+    #[test]
+    fn fen_round_trip() {
+        let z_table = Zobrist::new(1);
+        for preset in Preset::ALL {
+            let start = GameState::from_preset(preset, &z_table);
+            let parsed = GameState::from_fen(&start.to_fen(), preset.rules(), &z_table).unwrap();
+            assert_eq!(parsed.to_fen(), start.to_fen());
+            assert_eq!(parsed.hash, start.hash);
+        }
+        let fen = "3B3/3B3/3W3/BBWKWBB/3W3/3B3/3B3 w 12";
+        assert_eq!(position(fen, RuleSet::default()).to_fen(), fen);
+    }
+
+    /// Board as printed by display, without the side to move line.
+    fn diagram(state: &GameState) -> String {
+        let mut out = Vec::new();
+        state.display(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        text.lines().filter(|l| !l.ends_with("to move")).map(|l| format!("{}\n", l)).collect()
+    }
+
+    #[test]
+    fn diagram_round_trip() {
+        let z_table = Zobrist::new(1);
+        for preset in Preset::ALL {
+            let start = GameState::from_preset(preset, &z_table);
+            let mut out = Vec::new();
+            start.display(&mut out).unwrap();
+            let parsed = GameState::from_diagram(&String::from_utf8(out).unwrap(), 0, None, preset.rules(), &z_table).unwrap();
+            assert_eq!(parsed.to_fen(), start.to_fen());
+        }
+    }
+
+    #[test]
+    fn diagram_from_game_log() {
+        // Boards followed by the lines of play_games, which do not give the side to move.
+        let z_table = Zobrist::new(1);
+        let first = GameState::new(RuleSet::default(), &z_table);
+        let mut second = first.clone();
+        second.make_move(&Move::parse("a4-a2", 7).unwrap(), &z_table, true, &mut io::sink());
+        let log = format!("{}Bot is thinking...\nEngine plays a4-a2\n{}Playing random move d3-c3\n", diagram(&first), diagram(&second));
+
+        let parsed = GameState::from_diagram(&log, 1, Some(Player::White), RuleSet::default(), &z_table).unwrap();
+        assert_eq!(parsed.to_fen(), "3B3/3B3/3W3/1BWKWBB/3W3/B2B3/3B3 w 0");
+        assert_eq!(GameState::from_diagram(&log, 0, Some(Player::Black), RuleSet::default(), &z_table).unwrap().to_fen(), first.to_fen());
+        assert!(GameState::from_diagram(&log, 0, None, RuleSet::default(), &z_table).is_err());
+        assert!(GameState::from_diagram(&log, 2, Some(Player::Black), RuleSet::default(), &z_table).is_err());
+
+        // Side to move read from the line after the board, past the blank lines.
+        let log = format!("{}\nCurrent Player: White\n{}", diagram(&first), diagram(&second));
+        assert_eq!(GameState::from_diagram(&log, 0, None, RuleSet::default(), &z_table).unwrap().player, Player::White);
+        assert!(GameState::from_diagram(&log, 1, None, RuleSet::default(), &z_table).is_err());
+        let log = format!("{}b\n", diagram(&first));
+        assert_eq!(GameState::from_diagram(&log, 0, None, RuleSet::default(), &z_table).unwrap().player, Player::Black);
+        let log = format!("{}Player Black is thinking...\n", diagram(&first));
+        assert_eq!(GameState::from_diagram(&log, 0, None, RuleSet::default(), &z_table).unwrap().player, Player::Black);
+    }
+
+    #[test]
+    fn diagram_before_the_result() {
+        // The last board of an old log is followed by the result, which does not tell the side to move.
+        let z_table = Zobrist::new(1);
+        let last = position("7/7/7/7/7/3B3/K6 w 0", RuleSet::default());
+        for result in ["White wins!", "White wins! (the king escaped)", "Black wins! (the king was captured)"] {
+            let log = format!("{}{}\n", diagram(&last), result);
+            assert!(GameState::from_diagram(&log, 0, None, RuleSet::default(), &z_table).is_err(), "{}", result);
+            let parsed = GameState::from_diagram(&log, 0, Some(Player::Black), RuleSet::default(), &z_table).unwrap();
+            assert_eq!(parsed.player, Player::Black);
+        }
+    }
+
+    #[test]
+    fn perft_start_7x7() {
+        let z_table = Zobrist::new(1);
+        let start = GameState::new(RuleSet::default(), &z_table);
+        for (depth, nodes) in [(0, 1), (1, 40), (2, 960), (3, 39512), (4, 1007392)] {
+            assert_eq!(start.perft(depth, false, &z_table), nodes, "depth {}", depth);
+        }
+        assert_eq!(start.perft(4, true, &z_table), 1006488);
+    }
+
+    #[test]
+    fn perft_divide_matches_perft() {
+        let z_table = Zobrist::new(1);
+        let start = GameState::new(RuleSet::default(), &z_table);
+        for depth in 0..3 {
+            assert_eq!(start.perft_divide(depth, true, &z_table, &mut io::sink()), start.perft(depth, true, &z_table));
+        }
+    }
+
+    /// Plays a random game of up to plies moves. A third of the moves take back the mover's previous move
+    /// when they can, so that positions repeat. Returns the moves.
+    fn random_game(state: &mut GameState, plies: usize, seed: u64, z_table: &Zobrist) -> Vec<Move> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut moves = MoveList::new();
+        let mut played: Vec<Move> = Vec::new();
+        for _ in 0..plies {
+            if state.check_game_over(z_table).is_some() { break; }
+            state.get_legal_moves(&mut moves, false, z_table);
+            let back = played.len().checked_sub(2).map(|i| {
+                let (sr, sc, er, ec) = played[i].coords(state.geo.size);
+                Move::new(er, ec, sr, sc, state.geo.size)
+            });
+            let m = match back {
+                Some(back) if rng.random_range(0..3) == 0 && moves.contains(&back) => back,
+                _ => moves[rng.random_range(0..moves.len())],
+            };
+            assert_eq!(state.next_hash(&m, z_table), {
+                let mut next = state.clone();
+                next.make_move(&m, z_table, true, &mut io::sink());
+                next.hash
+            });
+            state.make_move(&m, z_table, true, &mut io::sink());
+            played.push(m);
+        }
+        played
+    }
+
+    /// What make_move and unmake_move have to restore.
+    fn snapshot(state: &GameState) -> (String, u64, bool, Option<usize>) {
+        (state.to_fen(), state.hash, state.repetition, state.repetition_dist)
+    }
+
+    #[test]
+    fn make_unmake_random_games() {
+        let z_table = Zobrist::new(1);
+        for preset in Preset::ALL {
+            // Repetitions do not end the game, so that it goes on through them.
+            let mut rules = preset.rules();
+            rules.repetition = RepetitionRule::Allowed;
+            for seed in 0..4 {
+                let mut state = GameState::from_preset(preset, &z_table);
+                state.rules = rules;
+                let moves = random_game(&mut state.clone(), 300, seed, &z_table);
+
+                let mut undos = Vec::new();
+                let mut snapshots = vec![snapshot(&state)];
+                let mut moves_list = MoveList::new();
+                for m in &moves {
+                    // The repetition filter of move generation agrees with make_move.
+                    state.get_legal_moves(&mut moves_list, false, &z_table);
+                    for &other in moves_list.iter() {
+                        let undo = state.make_move(&other, &z_table, true, &mut io::sink());
+                        let repeats = state.repetition;
+                        state.unmake_move(&undo);
+                        assert_eq!(state.repeats_history(&other, &z_table), repeats, "{:?} {}", preset, state.to_fen());
+                    }
+                    undos.push(state.make_move(m, &z_table, true, &mut io::sink()));
+                    // The hash updated move by move is the hash of the boards.
+                    let pieces = (state.black_pieces, state.white_pieces, state.king_piece);
+                    let key = GameState::hash_boards(state.geo.size, pieces, Symmetry::Identity, &z_table);
+                    let side = if state.player == Player::Black { z_table.black_to_move } else { 0 };
+                    assert_eq!(state.hash, key ^ side, "{:?} {}", preset, state.to_fen());
+                    snapshots.push(snapshot(&state));
+                }
+                assert!(snapshots.iter().any(|s| s.2), "{:?}: no repetition in the game", preset);
+
+                // A copy of the window (as for the parallel playouts) finds the same repetitions.
+                let mut window = GameState::from_preset(preset, &z_table);
+                window.clone_window_from(&state);
+                state.get_legal_moves(&mut moves_list, false, &z_table);
+                for m in moves_list.iter() {
+                    assert_eq!(window.repeats_history(m, &z_table), state.repeats_history(m, &z_table), "{:?} {}", preset, state.to_fen());
+                }
+
+                // Take back half of the game, on the state and on a clone, then play it again.
+                let half = moves.len() / 2;
+                let mut copy = state.clone();
+                for (i, undo) in undos.drain(half..).enumerate().collect::<Vec<_>>().into_iter().rev() {
+                    state.unmake_move(&undo);
+                    copy.unmake_move(&undo);
+                    assert_eq!(snapshot(&state), snapshots[half + i], "{:?}", preset);
+                    assert_eq!(snapshot(&copy), snapshots[half + i], "{:?}", preset);
+                }
+                for (i, m) in moves[half..].iter().enumerate() {
+                    state.make_move(m, &z_table, true, &mut io::sink());
+                    assert_eq!(snapshot(&state), snapshots[half + i + 1], "{:?}", preset);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unmake_restores_captures() {
+        let z_table = Zobrist::new(1);
+        // A white pawn taken against b7, and the king taken against b2.
+        for (fen, m) in [
+            ("1B5/1W5/7/7/5K1/7/1B5 b 0", "b1-b5"),
+            ("5W1/7/7/7/7/1BK4/3B3 b 0", "d1-d2"),
+        ] {
+            let mut state = position(fen, Preset::Brandubh.rules());
+            let before = snapshot(&state);
+            let undo = play(&mut state, m, &z_table);
+            assert!(undo.is_capture(), "{}", fen);
+            state.unmake_move(&undo);
+            assert_eq!(snapshot(&state), before);
+        }
+    }
+
+    #[test]
+    fn move_outcome_lists_the_captures() {
+        let z_table = Zobrist::new(1);
+        // d2 takes the king against b2, and the pawn on d3 against the empty throne.
+        let mut state = position("7/7/7/7/3W3/1BK4/3B3 b 0", Preset::Brandubh.rules());
+        let quiet = state.clone().move_piece(&Move::parse("b2-b3", 7).unwrap(), &z_table, true, &mut io::sink());
+        assert_eq!(quiet, MoveOutcome::default());
+        let outcome = state.move_piece(&Move::parse("d1-d2", 7).unwrap(), &z_table, true, &mut io::sink());
+        assert_eq!(outcome, MoveOutcome {
+            captures: vec![
+                Capture { row: 4, col: 3, piece: Piece::White },
+                Capture { row: 5, col: 2, piece: Piece::King },
+            ],
+            king_captured: true,
+            repetition: false,
+        });
+    }
+
+    #[test]
+    fn repetition_loses_for_white() {
+        let z_table = Zobrist::new(1);
+        let mut state = position("7/1B5/7/3K3/7/5W1/7 b 0", RuleSet::default());
+        for m in ["b6-a6", "f2-g2", "a6-b6"] {
+            state.make_move(&Move::parse(m, 7).unwrap(), &z_table, true, &mut io::sink());
+            assert!(!state.repetition);
+        }
+        // Moving back would recreate the starting position: White may not.
+        let back = Move::parse("g2-f2", 7).unwrap();
+        let mut moves = MoveList::new();
+        state.get_legal_moves(&mut moves, true, &z_table);
+        assert!(!moves.contains(&back));
+        state.get_legal_moves(&mut moves, false, &z_table);
+        assert!(moves.contains(&back));
+
+        let undo = state.make_move(&back, &z_table, true, &mut io::sink());
+        assert!(state.repetition);
+        assert_eq!(state.repetition_dist, Some(4));
+        assert_eq!(state.check_game_over(&z_table), Some(GameOutcome::win(Player::Black, EndReason::Repetition)));
+        state.unmake_move(&undo);
+        assert!(!state.repetition);
+        assert_eq!(state.check_game_over(&z_table), None);
+    }
+
+    #[test]
+    fn threefold_repetition_with_side_to_move() {
+        let z_table = Zobrist::new(1);
+        let rules = RuleSet { repetition: RepetitionRule::Draw, repetition_count: 3, repetition_side_to_move: true, ..RuleSet::default() };
+        let mut state = position("7/1B5/7/3K3/7/5W1/7 b 0", rules);
+        let cycle = ["b6-a6", "f2-g2", "a6-b6", "g2-f2"];
+        for (ply, m) in cycle.iter().chain(&cycle).enumerate() {
+            state.make_move(&Move::parse(m, 7).unwrap(), &z_table, true, &mut io::sink());
+            assert_eq!(state.repetition, ply == 7, "ply {}", ply + 1);
+        }
+        assert_eq!(state.repetition_dist, Some(8));
+        assert_eq!(state.check_game_over(&z_table), Some(GameOutcome::draw(EndReason::Repetition)));
+    }
+
+    #[test]
+    fn capture_closes_the_history_window() {
+        let z_table = Zobrist::new(1);
+        let mut state = position("7/2WB3/7/3K3/7/4W2/1B3W1 b 0", RuleSet::default());
+        let mut undos = Vec::new();
+        for m in ["b1-c1", "f1-f2", "c1-b1", "e2-e6", "b1-c1", "f2-f1"] {
+            undos.push(state.make_move(&Move::parse(m, 7).unwrap(), &z_table, true, &mut io::sink()));
+        }
+        // e2-e6 captured d6: the position after f2-f1 is not the starting one.
+        assert_eq!(state.black_pieces.count_ones(), 1);
+        assert!(!state.repetition);
+
+        // Once the capture is taken back, f2-f1 repeats the starting position again, on the state and on a clone.
+        let back = Move::parse("f2-f1", 7).unwrap();
+        let mut copy = state.clone();
+        for state in [&mut state, &mut copy] {
+            for undo in undos[3..].iter().rev() { state.unmake_move(undo); }
+            assert!(state.repeats_history(&back, &z_table));
+            state.make_move(&back, &z_table, true, &mut io::sink());
+            assert!(state.repetition);
+            assert_eq!(state.repetition_dist, Some(4));
+        }
+    }
+
+    #[test]
+    fn canonical_hash_of_symmetric_positions() {
+        let z_table = Zobrist::new(1);
+        let mut moves = MoveList::new();
+        for preset in Preset::ALL {
+            let mut state = GameState::from_preset(preset, &z_table);
+            random_game(&mut state, 9, 0, &z_table);
+            let n = state.geo.size;
+            let mut hashes = Vec::new();
+            for sym in Symmetry::ALL {
+                let pieces = (sym.apply_board(state.black_pieces, n), sym.apply_board(state.white_pieces, n), sym.apply_board(state.king_piece, n));
+                let image = GameState::from_boards(n, pieces, state.player, state.ply_count, state.rules, &z_table);
+                assert_eq!(image.canonical_hash(&z_table), state.canonical_hash(&z_table), "{:?} {:?}", preset, sym);
+                hashes.push(image.hash);
+            }
+            hashes.sort_unstable();
+            hashes.dedup();
+            assert!(hashes.len() > 1, "{:?}: symmetric position", preset);
+
+            // Predicted without making the move.
+            let keys = state.symmetric_keys(&z_table);
+            state.get_legal_moves(&mut moves, false, &z_table);
+            for m in moves.iter() {
+                let mut next = state.clone();
+                next.make_move(m, &z_table, true, &mut io::sink());
+                assert_eq!(state.canonical_next_hash(m, &keys, &z_table), next.canonical_hash(&z_table), "{:?}", preset);
+            }
+        }
+    }
+
+    #[test]
+    fn rule_10_only_when_nobody_can_win() {
+        let z_table = Zobrist::new(1);
+        // A lone king can still walk to a corner around two black pieces.
+        let armed = position("7/1B5/7/3K3/7/5B1/7 w 0", RuleSet::default());
+        assert!(!armed.is_insufficient_material_draw());
+        assert_eq!(armed.check_game_over(&z_table), None);
+
+        // An unarmed king can still walk to the edge.
+        let tablut = position("9/1B7/9/9/4K4/9/9/6B2/9 w 0", Preset::Tablut.rules());
+        assert!(tablut.white_can_win());
+        assert!(!tablut.is_insufficient_material_draw());
+
+        // Two black pieces can capture a king away from the throne, unless he has to be surrounded on four sides.
+        assert!(tablut.black_can_win());
+        let four_sides = RuleSet { king_capture: KingCapture::FourSides, ..Preset::Tablut.rules() };
+        assert!(!position("9/1B7/9/9/4K4/9/9/6B2/9 w 0", four_sides).black_can_win());
+    }
+
+    #[test]
+    fn rule_10_fortress() {
+        let z_table = Zobrist::new(1);
+        // Black encloses a white fort on the edge: neither side can get to the pieces of the other.
+        let fort = "11/11/11/11/11/11/11/3BBB5/2BWWWB4/2BW1WB4/2BWKWB4 w 0";
+        let state = position(fort, RuleSet::default());
+        assert!(!state.white_can_win());
+        assert!(!state.black_can_win());
+        assert_eq!(state.check_game_over(&z_table), Some(GameOutcome::draw(EndReason::InsufficientMaterial)));
+
+        // With a gap in the enclosure the king can still get out.
+        let open = position("11/11/11/11/11/11/11/3B1B5/2BWWWB4/2BW1WB4/2BWKWB4 w 0", RuleSet::default());
+        assert!(open.white_can_win());
+        assert_eq!(open.check_game_over(&z_table), None);
+
+        // A lone king enclosed on the edge can be blocked, even when he cannot be captured there.
+        let pocket = position("9/9/9/9/9/9/9/3BBB3/2B1K1B2 w 0", Preset::ArdRi.rules());
+        assert!(!pocket.white_can_win());
+        assert!(pocket.black_can_win());
+    }
+
+    #[test]
+    fn invalid_fen() {
+        for fen in [
+            "",
+            "3B3/3B3/3W3/BBWKWBB/3W3/3B3/3B3",
+            "3B3/3B3/3W3/BBWKWBB/3W3/3B3/3B3 x",
+            "3B3/3B3/3W3/BBWKWBB/3W3/3B3/3B3 b z",
+            "3B3/3B3/3W3/BBWKWBB/3W3/3B3 b",
+            "3B3/3B3/3W3/BBWKWBB/3W3/3B3/3B4 b",
+            "3B3/3B3/3W3/BBWXWBB/3W3/3B3/3B3 b",
+            "3B3/3B3/3W3/BBWWWBB/3W3/3B3/3B3 b",
+            "B6/3B3/3W3/BBWKWBB/3W3/3B3/3B3 b",
+            // A run of empty squares too long to fit in a usize.
+            "99999999999999999999999999/3B3/3W3/BBWKWBB/3W3/3B3/3B3 b",
+        ] {
+            assert!(GameState::from_fen(fen, RuleSet::default(), &Zobrist::new(1)).is_err(), "{} parsed", fen);
+        }
+    }
+}
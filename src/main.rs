@@ -1,18 +1,31 @@
 
+pub mod bitboard;
 pub mod hnefatafl;
 pub mod zobrist;
 pub mod transposition;
 pub mod mcts;
-
+pub mod rules;
+pub mod moves;
+pub mod outcome;
+pub mod symmetry;
+pub mod clock;
+pub mod engine;
+
+use std::collections::HashMap;
 use std::fs::File;
 use std::{fs, io};
 use std::io::{BufWriter, Write};
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use rand::prelude::IndexedRandom;
-use hnefatafl::GameState;
+use hnefatafl::{GameState, Undo};
 use mcts::MCTS;
-use crate::mcts::SimulationType;
+use crate::clock::{GameClock, TimeControl, TimeManager};
+use crate::engine::{Command, EngineHandle, Report};
+use crate::mcts::{SearchLimits, SimulationType};
+use crate::moves::MoveList;
+use crate::outcome::{EndReason, GameOutcome, Player};
+use crate::rules::{Preset, RuleSet};
 
 #[derive(Copy, Clone)]
 enum GameMode {
@@ -21,8 +34,9 @@ enum GameMode {
     BotVsRandom,
 }
 
-fn play_game(engine: &mut MCTS, mode: GameMode, bot_side: char, to_file: bool, file_name: &str) {
-    let mut game = GameState::new(&engine.z_table);
+/// Under a time control, the engine's limits come from the time manager and a flag fall loses the game.
+#[allow(clippy::too_many_arguments)]
+fn play_game(engine: &mut MCTS, limits: SearchLimits, time_control: Option<TimeControl>, mode: GameMode, bot_side: Player, mut game: GameState, to_file: bool, file_name: &str) {
 
     // 1. Create the base writer (Stdout or File)
     let writer: Box<dyn Write> = if to_file {
@@ -36,24 +50,46 @@ fn play_game(engine: &mut MCTS, mode: GameMode, bot_side: char, to_file: bool, f
 
     let time = Instant::now();
     let mut moves_count = 0;
+    // Undo records of the moves played, for takebacks.
+    let mut played = Vec::new();
+    let mut clock = time_control.map(GameClock::new);
+    let time_manager = TimeManager::default();
     loop {
         // 3. Pass the buffered writer to display
         game.display(&mut buffered_writer).expect("Output failed");
+        if let Some(clock) = &clock {
+            writeln!(buffered_writer, "{}", clock).expect("could not write to output");
+        }
 
         // 4. IMPORTANT: Manual flush
         // Because BufWriter holds data until it's full (usually 8KB),
         // you must flush to ensure the board actually appears to the user.
         buffered_writer.flush().expect("Flush failed");
 
-        if let Some(result) = game.check_game_over_log(&mut buffered_writer) {
-            announce_result(result, &mut buffered_writer).expect("could not writer ending message");
+        if let Some(outcome) = game.check_game_over_log(&engine.z_table, &mut buffered_writer) {
+            writeln!(buffered_writer, "{}", outcome).expect("could not writer ending message");
             buffered_writer.flush().expect("Flush failed");
             break;
         }
 
+        let mover = game.player;
+        let limits = match &mut clock {
+            Some(clock) => {
+                clock.start_turn();
+                time_manager.limits(&game, clock)
+            }
+            None => limits,
+        };
         match mode {
             GameMode::HumanVsHuman => {
-                game.human_move(&engine.z_table, &mut buffered_writer);
+                match game.human_move(&engine.z_table, &mut buffered_writer) {
+                    Some(undo) => played.push(undo),
+                    None => {
+                        if !charge_takeback(&mut clock, mover, &mut buffered_writer) { break; }
+                        moves_count -= take_back(&mut game, &mut played, 1, &mut buffered_writer);
+                        continue;
+                    }
+                }
             }
 
             GameMode::HumanVsBot => {
@@ -61,9 +97,18 @@ fn play_game(engine: &mut MCTS, mode: GameMode, bot_side: char, to_file: bool, f
                     writeln!(buffered_writer, "Bot is thinking...").expect("could not write to output");
                     buffered_writer.flush().expect("Flush failed");
 
-                    engine.computer_move(&mut game, &mut buffered_writer);
+                    played.push(engine.computer_move(&mut game, &limits, &mut buffered_writer));
                 } else {
-                    game.human_move(&engine.z_table, &mut buffered_writer);
+                    // The engine ponders while the human thinks.
+                    match engine.ponder_while(game.clone(), |z_table| game.human_move(z_table, &mut buffered_writer)) {
+                        Some(undo) => played.push(undo),
+                        None => {
+                            // Take back the bot answer and the human move.
+                            if !charge_takeback(&mut clock, mover, &mut buffered_writer) { break; }
+                            moves_count -= take_back(&mut game, &mut played, 2, &mut buffered_writer);
+                            continue;
+                        }
+                    }
                 }
             }
 
@@ -72,17 +117,22 @@ fn play_game(engine: &mut MCTS, mode: GameMode, bot_side: char, to_file: bool, f
                     writeln!(buffered_writer, "Bot is thinking...").expect("could not write to output");
                     buffered_writer.flush().expect("Flush failed");
 
-                    engine.computer_move(&mut game, &mut buffered_writer);
+                    engine.computer_move(&mut game, &limits, &mut buffered_writer);
                 } else {
-                    writeln!(buffered_writer, "Playing random move").expect("could not write to output");
                     let mut rng = rand::rng();
-                    let mut moves = Vec::with_capacity(mcts::MAX_MOVES);
-                    game.get_legal_moves(&mut moves, false);
+                    let mut moves = MoveList::new();
+                    game.get_legal_moves(&mut moves, false, &engine.z_table);
                     let random_move = moves.choose(&mut rng).unwrap();
+                    writeln!(buffered_writer, "Playing random move {}", random_move).expect("could not write to output");
                     game.move_piece(random_move, &engine.z_table, false, &mut buffered_writer);
                 }
             }
         }
+        if clock.as_mut().is_some_and(|clock| !clock.end_turn(mover)) {
+            writeln!(buffered_writer, "{}", GameOutcome::win(mover.opponent(), EndReason::TimeForfeit)).expect("could not writer ending message");
+            buffered_writer.flush().expect("Flush failed");
+            break;
+        }
         moves_count += 1;
     }
     let elapsed_time = Instant::now() - time;
@@ -91,22 +141,52 @@ fn play_game(engine: &mut MCTS, mode: GameMode, bot_side: char, to_file: bool, f
     buffered_writer.flush().expect("Flush failed");
 }
 
-fn play_games(mode: GameMode, bot_side: char, game_count: usize, folder_name: &str) {
+/// Under a time control, a takeback costs the player the time he spent before asking for it.
+/// Returns false if his time ran out, and writes the result of the game.
+fn charge_takeback<W: Write>(clock: &mut Option<GameClock>, mover: Player, writer: &mut W) -> bool {
+    if clock.as_mut().is_none_or(|clock| clock.stop_turn(mover)) { return true; }
+    writeln!(writer, "{}", GameOutcome::win(mover.opponent(), EndReason::TimeForfeit)).expect("could not writer ending message");
+    writer.flush().expect("Flush failed");
+    false
+}
+
+/// Take back the last `plies` moves of the game, if that many were played.
+/// Returns the number of moves taken back.
+fn take_back<W: Write>(game: &mut GameState, played: &mut Vec<Undo>, plies: usize, writer: &mut W) -> usize {
+    if played.len() < plies {
+        writeln!(writer, "Nothing to take back.").expect("could not write to output");
+        return 0;
+    }
+    for _ in 0..plies {
+        let undo = played.pop().unwrap();
+        game.unmake_move(&undo);
+    }
+    plies
+}
+
+fn play_games(mode: GameMode, bot_side: Player, game_count: usize, folder_name: &str) {
     fs::create_dir_all(folder_name).expect("could not create folder");
 
     let time = Instant::now();
     
     for i in 0..game_count {
-        let mut engine = MCTS::new(0xCAFEBABE, 50_000, SimulationType::ParallelHeavy(8));
+        let mut engine = MCTS::new(0xCAFEBABE, SimulationType::ParallelHeavy(8));
         let file_name = format!("{}/{}.txt", folder_name, i);
-        play_game(&mut engine, mode, bot_side,true, &file_name);
+        let game = GameState::new(RuleSet::default(), &engine.z_table);
+        play_game(&mut engine, SearchLimits::iterations(50_000), None, mode, bot_side, game, true, &file_name);
     }
     let elapsed_time = Instant::now() - time;
     println!("Total time for {} games: {}", game_count, elapsed_time.as_secs_f64());
 }
 
-fn play_bot_vs_bot(white_engine: &mut MCTS, black_engine: &mut MCTS, to_file: bool, file_name: &str) -> char {
-    let mut game = GameState::new(&white_engine.z_table);
+/// Each engine searches its moves within its own limits.
+/// Under a time control, the limits come from the time manager instead, and a flag fall loses the game.
+fn play_bot_vs_bot(white: (&mut MCTS, SearchLimits), black: (&mut MCTS, SearchLimits), time_control: Option<TimeControl>, to_file: bool, file_name: &str) -> GameOutcome {
+    let (white_engine, white_limits) = white;
+    let (black_engine, black_limits) = black;
+    let mut clock = time_control.map(GameClock::new);
+    let time_manager = TimeManager::default();
+    let mut game = GameState::new(RuleSet::default(), &white_engine.z_table);
 
     let writer: Box<dyn Write> = if to_file {
         Box::new(File::create(file_name).expect("Failed to create log file"))
@@ -117,26 +197,46 @@ fn play_bot_vs_bot(white_engine: &mut MCTS, black_engine: &mut MCTS, to_file: bo
     let mut buffered_writer = BufWriter::new(writer);
     let time = Instant::now();
     let mut moves_count = 0;
-    let winner;
+    let result;
     loop {
         game.display(&mut buffered_writer).expect("Output failed");
+        if let Some(clock) = &clock {
+            writeln!(buffered_writer, "{}", clock).expect("Write failed");
+        }
         buffered_writer.flush().expect("Flush failed");
 
-        if let Some(result) = game.check_game_over_log(&mut buffered_writer) {
-            announce_result(result, &mut buffered_writer).expect("Ending message failed");
+        if let Some(outcome) = game.check_game_over_log(&white_engine.z_table, &mut buffered_writer) {
+            writeln!(buffered_writer, "{}", outcome).expect("Ending message failed");
             buffered_writer.flush().expect("Flush failed");
-            winner = result;
+            result = outcome;
             break;
         }
 
         writeln!(buffered_writer, "Player {} is thinking...", game.player).expect("Write failed");
         buffered_writer.flush().expect("Flush failed");
 
-        // Alternate engines based on the current player
-        if game.player == 'W' {
-            white_engine.computer_move(&mut game, &mut buffered_writer);
+        let mover = game.player;
+        let (engine, limits, opponent) = if mover == Player::White {
+            (&mut *white_engine, white_limits, &mut *black_engine)
         } else {
-            black_engine.computer_move(&mut game, &mut buffered_writer);
+            (&mut *black_engine, black_limits, &mut *white_engine)
+        };
+        let limits = match &mut clock {
+            Some(clock) => {
+                clock.start_turn();
+                time_manager.limits(&game, clock)
+            }
+            None => limits,
+        };
+
+        // Alternate engines based on the current player, the other one pondering meanwhile.
+        opponent.ponder_while(game.clone(), |_| engine.computer_move(&mut game, &limits, &mut buffered_writer));
+
+        if clock.as_mut().is_some_and(|clock| !clock.end_turn(mover)) {
+            result = GameOutcome::win(mover.opponent(), EndReason::TimeForfeit);
+            writeln!(buffered_writer, "{}", result).expect("Ending message failed");
+            buffered_writer.flush().expect("Flush failed");
+            break;
         }
 
         moves_count += 1;
@@ -146,7 +246,7 @@ fn play_bot_vs_bot(white_engine: &mut MCTS, black_engine: &mut MCTS, to_file: bo
     writeln!(buffered_writer, "Total moves: {}", moves_count).ok();
     writeln!(buffered_writer, "Total time: {:.2}s", elapsed_time.as_secs_f64()).ok();
     buffered_writer.flush().ok();
-    winner
+    result
 }
 
 fn play_bot_games(game_count: usize, folder_name: &str) {
@@ -157,13 +257,13 @@ fn play_bot_games(game_count: usize, folder_name: &str) {
     println!("{} games will be played with both sides having 200_000 iterations per move", game_count);
     let total_time = Instant::now();
     for i in 0..game_count {
-        let mut engine_white = MCTS::new(0xCAFEBABE, 100_000, SimulationType::ParallelHeavy(8));
-        let mut engine_black = MCTS::new(0xDEADBEEF, 400_000, SimulationType::ParallelHeavy(8));
+        let mut engine_white = MCTS::new(0xCAFEBABE, SimulationType::ParallelHeavy(8));
+        let mut engine_black = MCTS::new(0xDEADBEEF, SimulationType::ParallelHeavy(8));
 
         let file_name = format!("{}/{}.txt", folder_name, i);
 
-        let result = play_bot_vs_bot(&mut engine_white, &mut engine_black, true, &file_name);
-        if result == 'B' { black_wins += 1; } else { white_wins += 1; }
+        let result = play_bot_vs_bot((&mut engine_white, SearchLimits::iterations(100_000)), (&mut engine_black, SearchLimits::iterations(400_000)), None, true, &file_name);
+        if result.winner == Some(Player::Black) { black_wins += 1; } else { white_wins += 1; }
     }
     println!("White won {} games", white_wins);
     println!("Black won {} games", black_wins);
@@ -183,19 +283,57 @@ fn play_bot_games(game_count: usize, folder_name: &str) {
 
     let total_time = Instant::now();
     for i in 0..game_count {
-        let mut engine_white = MCTS::new(0xCAFEBABE, white_iterations, SimulationType::ParallelHeavy(8));
-        let mut engine_black = MCTS::new(0xDEADBEEF, black_iterations, SimulationType::ParallelHeavy(8));
+        let mut engine_white = MCTS::new(0xCAFEBABE, SimulationType::ParallelHeavy(8));
+        let mut engine_black = MCTS::new(0xDEADBEEF, SimulationType::ParallelHeavy(8));
 
         let file_name = format!("{}/{}.txt", &new_folder_name, i);
 
-        let result = play_bot_vs_bot(&mut engine_white, &mut engine_black, true, &file_name);
-        if result == 'B' { black_wins += 1; } else { white_wins += 1; }
+        let result = play_bot_vs_bot((&mut engine_white, SearchLimits::iterations(white_iterations)), (&mut engine_black, SearchLimits::iterations(black_iterations)), None, true, &file_name);
+        if result.winner == Some(Player::Black) { black_wins += 1; } else { white_wins += 1; }
     }
     println!("White won {} games", white_wins);
     println!("Black won {} games", black_wins);
     println!("Finished {} games in {:.2}s", game_count, total_time.elapsed().as_secs_f64());
 }
 
+/// Engine vs engine games under a time control.
+/// With ponder, each engine searches on the time of the other (see MCTS::ponder_while).
+fn play_timed_bot_games(game_count: usize, time_control: TimeControl, ponder: bool, folder_name: &str) {
+    fs::create_dir_all(folder_name).expect("could not create folder");
+
+    let mut white_wins = 0;
+    let mut black_wins = 0;
+    let mut draws = 0;
+    // Why the games ended, flag falls included.
+    let mut reasons: HashMap<EndReason, usize> = HashMap::new();
+    let total_time = Instant::now();
+    for i in 0..game_count {
+        let mut engine_white = MCTS::new(0xCAFEBABE + i as u64, SimulationType::ParallelHeavy(8));
+        let mut engine_black = MCTS::new(0xDEADBEEF + i as u64, SimulationType::ParallelHeavy(8));
+        engine_white.ponder = ponder;
+        engine_black.ponder = ponder;
+
+        let file_name = format!("{}/{}.txt", folder_name, i);
+
+        // The limits only apply without a time control.
+        let limits = SearchLimits::iterations(100_000);
+        let result = play_bot_vs_bot((&mut engine_white, limits), (&mut engine_black, limits), Some(time_control), true, &file_name);
+        *reasons.entry(result.reason).or_insert(0) += 1;
+        match result.winner {
+            Some(Player::White) => white_wins += 1,
+            Some(Player::Black) => black_wins += 1,
+            None => draws += 1,
+        }
+    }
+    println!("White won {} games", white_wins);
+    println!("Black won {} games", black_wins);
+    println!("Draws: {}", draws);
+    for (reason, count) in &reasons {
+        println!("Ended by {}: {}", reason, count);
+    }
+    println!("Finished {} games in {:.2}s", game_count, total_time.elapsed().as_secs_f64());
+}
+
 use rayon::prelude::*; // Ensure this is at the top of your file
 
 fn play_bot_games_parallel(thread_count: usize, game_count: usize) {
@@ -216,15 +354,15 @@ fn play_bot_games_parallel(thread_count: usize, game_count: usize) {
         for _ in 0..game_count {
             let white_seed = 0xCAFEBABE + (thread_id as u64 * 100);
             let black_seed = 0xDEADBEEF + (thread_id as u64 * 100);
-            let mut engine_white = MCTS::new(white_seed, white_iterations, SimulationType::ParallelHeavy(8));
-            let mut engine_black = MCTS::new(black_seed, black_iterations, SimulationType::ParallelHeavy(8));
+            let mut engine_white = MCTS::new(white_seed, SimulationType::ParallelHeavy(8));
+            let mut engine_black = MCTS::new(black_seed, SimulationType::ParallelHeavy(8));
 
             // Use your existing logic to play the game
             for i in 0..game_count {
                 let run_id = thread_count * thread_id + i;
                 let file_name = format!("{}/{}", folder_name, run_id);
-                let result = play_bot_vs_bot(&mut engine_white, &mut engine_black, true, &file_name);
-                if result == 'B' {
+                let result = play_bot_vs_bot((&mut engine_white, SearchLimits::iterations(white_iterations)), (&mut engine_black, SearchLimits::iterations(black_iterations)), None, true, &file_name);
+                if result.winner == Some(Player::Black) {
                     let mut help = black_wins.lock().unwrap();
                     *help += 1;
                 } else {
@@ -261,16 +399,16 @@ fn play_increasing_bot_games(thread_count: usize, folder_name: &str) {
             let white_seed = 0xCAFEBABE + (thread_id as u64 * 100) + attempt;
             let black_seed = 0xDEADBEEF + (thread_id as u64 * 100) + attempt;
 
-            let mut engine_white = MCTS::new(white_seed, white_iterations, SimulationType::ParallelHeavy(8));
-            let mut engine_black = MCTS::new(black_seed, black_iterations, SimulationType::ParallelHeavy(8));
+            let mut engine_white = MCTS::new(white_seed, SimulationType::ParallelHeavy(8));
+            let mut engine_black = MCTS::new(black_seed, SimulationType::ParallelHeavy(8));
 
             // Create a unique filename for this specific attempt
             let file_name = format!("{}/trial_{}_iters_{}.txt", folder_name, thread_id, white_iterations);
 
             // Use your existing logic to play the game
-            let result = play_bot_vs_bot(&mut engine_white, &mut engine_black, true, &file_name);
+            let result = play_bot_vs_bot((&mut engine_white, SearchLimits::iterations(white_iterations)), (&mut engine_black, SearchLimits::iterations(black_iterations)), None, true, &file_name);
 
-            if result == 'W' {
+            if result.winner == Some(Player::White) {
                 // We use a standard println! here; Rayon handles thread-safe stdout locking
                 println!("Trial {}: White WON with {} iterations (Attempt {})", thread_id, white_iterations, attempt);
                 break;
@@ -287,17 +425,6 @@ fn play_increasing_bot_games(thread_count: usize, folder_name: &str) {
     println!("Finished all trials in {:.2}s", total_time.elapsed().as_secs_f64());
 }
 
-fn announce_result<W: Write>(result: char, writer: &mut W) -> io::Result<()> {
-    match result {
-        'W' => writeln!(writer, "White wins!")?,
-        'B' => writeln!(writer, "Black wins!")?,
-        'D' => writeln!(writer, "Draw.")?,
-        'E' => writeln!(writer, "Game ended with an error.")?,
-        _ => {}
-    }
-    Ok(())
-}
-
 fn run_simulation_test(
     test_name: &str,
     games_per_side: usize,
@@ -308,11 +435,15 @@ fn run_simulation_test(
     let mut wins_a = 0;
     let mut wins_b = 0;
     let mut draws = 0;
+    // Why the games ended.
+    let mut reasons: HashMap<EndReason, usize> = HashMap::new();
 
     println!("============================================================");
     println!("Starting Test: {}", test_name);
     println!("Config A: {:?} @ {} iters", config_a.0, config_a.1);
     println!("Config B: {:?} @ {} iters", config_b.0, config_b.1);
+    let limits_a = SearchLimits::iterations(config_a.1);
+    let limits_b = SearchLimits::iterations(config_b.1);
     println!("============================================================");
 
     let setup_dir = format!("{}/{}", folder_root, test_name.replace(" ", "_"));
@@ -324,15 +455,16 @@ fn run_simulation_test(
         let file_name = format!("{}/game_ph1_{}.txt", setup_dir, i);
         
         // Ensure distinct seeds
-        let mut engine_a = MCTS::new(0xCAFE + i as u64, config_a.1, config_a.0);
-        let mut engine_b = MCTS::new(0xBEEF + i as u64, config_b.1, config_b.0);
+        let mut engine_a = MCTS::new(0xCAFE + i as u64, config_a.0);
+        let mut engine_b = MCTS::new(0xBEEF + i as u64, config_b.0);
 
-        let result = play_bot_vs_bot(&mut engine_a, &mut engine_b, true, &file_name);
+        let result = play_bot_vs_bot((&mut engine_a, limits_a), (&mut engine_b, limits_b), None, true, &file_name);
+        *reasons.entry(result.reason).or_insert(0) += 1;
         
-        match result {
-            'W' => { wins_a += 1; print!("A"); },
-            'B' => { wins_b += 1; print!("B"); },
-             _  => { draws += 1; print!("D"); },
+        match result.winner {
+            Some(Player::White) => { wins_a += 1; print!("A"); },
+            Some(Player::Black) => { wins_b += 1; print!("B"); },
+            None => { draws += 1; print!("D"); },
         }
         io::stdout().flush().unwrap();
     }
@@ -344,15 +476,16 @@ fn run_simulation_test(
         let file_name = format!("{}/game_ph2_{}.txt", setup_dir, i);
         
         // Ensure distinct seeds
-        let mut engine_b = MCTS::new(0xCAFE + 1000 + i as u64, config_b.1, config_b.0);
-        let mut engine_a = MCTS::new(0xBEEF + 1000 + i as u64, config_a.1, config_a.0);
+        let mut engine_b = MCTS::new(0xCAFE + 1000 + i as u64, config_b.0);
+        let mut engine_a = MCTS::new(0xBEEF + 1000 + i as u64, config_a.0);
 
-        let result = play_bot_vs_bot(&mut engine_b, &mut engine_a, true, &file_name);
+        let result = play_bot_vs_bot((&mut engine_b, limits_b), (&mut engine_a, limits_a), None, true, &file_name);
+        *reasons.entry(result.reason).or_insert(0) += 1;
         
-        match result {
-            'W' => { wins_b += 1; print!("B"); },
-            'B' => { wins_a += 1; print!("A"); },
-             _  => { draws += 1; print!("D"); },
+        match result.winner {
+            Some(Player::White) => { wins_b += 1; print!("B"); },
+            Some(Player::Black) => { wins_a += 1; print!("A"); },
+            None => { draws += 1; print!("D"); },
         }
         io::stdout().flush().unwrap();
     }
@@ -363,14 +496,95 @@ fn run_simulation_test(
     println!("Config A Wins: {}", wins_a);
     println!("Config B Wins: {}", wins_b);
     println!("Draws:         {}", draws);
+    for (reason, count) in &reasons {
+        println!("Ended by {}: {}", reason, count);
+    }
     println!("------------------------------------------------------------\n");
 }
 
+/// Text front end to the engine thread. One command per line:
+/// position [<fen>], go [<seconds>], stop, set <option> <value>, quit.
+/// Reports are printed as they arrive, while the engine keeps searching.
+fn run_console() {
+    let engine = MCTS::new(0xCAFEBABE, SimulationType::ParallelHeavy(8));
+    let z_table = engine.z_table.clone();
+    let start = GameState::new(RuleSet::default(), &z_table);
+    let (handle, reports) = EngineHandle::spawn(engine, start);
+
+    let printer = std::thread::spawn(move || {
+        for report in reports {
+            match report {
+                Report::Progress(p) => match p.best {
+                    Some(best) => println!("info playouts {} time {:.2}s best {} visits {}", p.playouts, p.elapsed.as_secs_f64(), best, p.best_visits),
+                    None => println!("info playouts {} time {:.2}s", p.playouts, p.elapsed.as_secs_f64()),
+                },
+                Report::BestMove(m) => println!("bestmove {}", m),
+                Report::GameOver(outcome) => println!("gameover {}", outcome),
+            }
+        }
+    });
+
+    for line in io::stdin().lines() {
+        let line = line.expect("could not read input");
+        let (name, args) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        let command = match name {
+            "position" if args.trim().is_empty() => Ok(Command::Position(Box::new(GameState::new(RuleSet::default(), &z_table)))),
+            "position" => GameState::from_fen(args, RuleSet::default(), &z_table).map(|state| Command::Position(Box::new(state))),
+            // Without a time, the search runs until stop.
+            "go" if args.trim().is_empty() => Ok(Command::Go(SearchLimits::unlimited())),
+            "go" => args.trim().parse::<f64>()
+                .map(|seconds| Command::Go(SearchLimits::time(Duration::from_secs_f64(seconds))))
+                .map_err(|_| format!("invalid time {}", args)),
+            "stop" => Ok(Command::Stop),
+            "set" => args.parse().map(Command::SetOption),
+            "quit" => break,
+            "" => continue,
+            other => Err(format!("unknown command {}", other)),
+        };
+        match command {
+            Ok(command) => {
+                if let Command::Position(state) = &command {
+                    state.display(&mut io::stdout()).expect("Output failed");
+                }
+                handle.send(command);
+            }
+            Err(e) => println!("error: {}", e),
+        }
+    }
+    // Stops the search and ends the engine thread, which closes the reports.
+    drop(handle);
+    printer.join().expect("printer thread panicked");
+}
+
+/// Position of a board from a game log (as written by play_game or play_bot_vs_bot),
+/// asking which board and, if the log does not say, the side to move.
+fn load_log_position(file_name: &str, z_table: &zobrist::Zobrist) -> GameState {
+    let log = fs::read_to_string(file_name).expect("could not read the game log");
+
+    println!("Board number in the log? (counting from 1, empty for the first)");
+    let mut index = String::new();
+    io::stdin().read_line(&mut index).unwrap();
+    let index = if index.trim().is_empty() { 0 } else {
+        index.trim().parse::<usize>().expect("board number has to be given as a number").saturating_sub(1)
+    };
+
+    println!("Side to move? (b or w, empty to read it from the log)");
+    let mut side = String::new();
+    io::stdin().read_line(&mut side).unwrap();
+    let player = match side.trim() {
+        "b" | "B" => Some(Player::Black),
+        "w" | "W" => Some(Player::White),
+        _ => None,
+    };
+
+    GameState::from_diagram(&log, index, player, RuleSet::default(), z_table).expect("invalid board")
+}
+
 fn main() {
 
     println!("Welcome to Hnefatafl!\n");
-    println!("Enter positions in the following format:");
-    println!("start_row start_col end_row end_col\n");
+    println!("Enter moves in tafl notation (files from a on the left, ranks from 1 at the bottom):");
+    println!("a4-a2\n");
 
     println!("type:");
     println!("1 -> human vs bot");
@@ -380,6 +594,9 @@ fn main() {
     println!("5 -> bot vs bot (increasing iterations)");
     println!("6 -> bot vs bot (threads)");
     println!("7 -> simulation comparison");
+    println!("8 -> perft (move generation test)");
+    println!("9 -> bot vs bot (time control)");
+    println!("10 -> engine console (position, go, stop, set, quit)");
     let mut input = String::new();
     io::stdin().read_line(&mut input).unwrap();
 
@@ -396,9 +613,9 @@ fn main() {
 
         let game_count : usize = input.trim().parse().expect("amount of games has to be given as a number");
         println!("Starting {} games of random vs engine on white", game_count);
-        play_games(mode, 'W', game_count, "random_vs_engine_on_white");
+        play_games(mode, Player::White, game_count, "random_vs_engine_on_white");
         println!("Starting {} games of random vs engine on black", game_count);
-        play_games(mode, 'B', game_count, "random_vs_engine_on_black");
+        play_games(mode, Player::Black, game_count, "random_vs_engine_on_black");
     } else if input.trim() == "4" {
         println!("How many games should be played?");
         let mut input = String::new();
@@ -427,6 +644,51 @@ fn main() {
         let game_count : usize = input2.trim().parse().expect("amount of games has to be given as a number");
         println!("Starting {} threads playing {} games of engine vs engine each", thread_count, game_count);
         play_bot_games_parallel(thread_count, game_count);
+    } else if input.trim() == "9" {
+        println!("Time control? (minutes+increment in seconds, e.g. 1+1)");
+        let mut time_control = String::new();
+        io::stdin().read_line(&mut time_control).unwrap();
+        let time_control: TimeControl = time_control.parse().expect("invalid time control");
+
+        println!("How many games should be played?");
+        let mut input = String::new();
+        io::stdin().read_line(&mut input).unwrap();
+
+        let game_count : usize = input.trim().parse().expect("amount of games has to be given as a number");
+
+        println!("Ponder on the opponent's time? (y/n, empty for no)");
+        let mut ponder = String::new();
+        io::stdin().read_line(&mut ponder).unwrap();
+        let ponder = ponder.trim().eq_ignore_ascii_case("y");
+
+        println!("Starting {} games of engine vs engine at {}", game_count, time_control);
+        play_timed_bot_games(game_count, time_control, ponder, "timed_bots");
+    } else if input.trim() == "10" {
+        run_console();
+    } else if input.trim() == "8" {
+        println!("Position? (empty for the 7x7 start)");
+        let mut position = String::new();
+        io::stdin().read_line(&mut position).unwrap();
+
+        println!("Depth?");
+        let mut depth = String::new();
+        io::stdin().read_line(&mut depth).unwrap();
+        let depth: usize = depth.trim().parse().expect("depth has to be given as a number");
+
+        let z_table = zobrist::Zobrist::new(0xCAFEBABE);
+        let game = if position.trim().is_empty() {
+            GameState::new(RuleSet::default(), &z_table)
+        } else {
+            GameState::from_fen(&position, RuleSet::default(), &z_table).expect("invalid position")
+        };
+
+        // Without repetition filter (all moves) and with it (as used by the engine).
+        let time = Instant::now();
+        game.perft_divide(depth, false, &z_table, &mut io::stdout());
+        println!("All moves: {:.2}s\n", time.elapsed().as_secs_f64());
+        let time = Instant::now();
+        let nodes = game.perft(depth, true, &z_table);
+        println!("Nodes without repetitions: {} ({:.2}s)", nodes, time.elapsed().as_secs_f64());
     } else if input.trim() == "7" {
         let games_per_side = 5;
         let iteration_tiers = [100_000, 200_000, 400_000];
@@ -476,7 +738,41 @@ fn main() {
         }
         println!("\nAll benchmarks complete.");
     } else {
-        let mut engine = MCTS::new(0xCAFEBABE, 200_000, SimulationType::ParallelHeavy(8));
-        play_game(&mut engine, mode, 'W', false, "");
+        let presets: Vec<String> = Preset::ALL.iter()
+            .map(|p| format!("{} ({}x{})", p.name(), p.board_size(), p.board_size()))
+            .collect();
+        println!("Variant? {}, board size (7, 9, 11 or 13), position (e.g. 3B3/3B3/3W3/BBWKWBB/3W3/3B3/3B3 b 0) or game log file", presets.join(", "));
+        let mut input = String::new();
+        io::stdin().read_line(&mut input).unwrap();
+
+        println!("Time control? (minutes+increment in seconds, e.g. 5+3, empty for none)");
+        let mut time_control = String::new();
+        io::stdin().read_line(&mut time_control).unwrap();
+        let time_control = if time_control.trim().is_empty() {
+            None
+        } else {
+            Some(time_control.parse::<TimeControl>().expect("invalid time control"))
+        };
+
+        let mut engine = MCTS::new(0xCAFEBABE, SimulationType::ParallelHeavy(8));
+        engine.ponder = true;
+        // Without a time control, a fixed thinking time whatever the position.
+        let limits = SearchLimits::time(Duration::from_secs(5));
+        let game = if let Some(preset) = Preset::from_name(&input) {
+            GameState::from_preset(preset, &engine.z_table)
+        } else if std::path::Path::new(input.trim()).is_file() {
+            load_log_position(input.trim(), &engine.z_table)
+        } else if input.contains('/') {
+            GameState::from_fen(&input, RuleSet::default(), &engine.z_table).expect("invalid position")
+        } else {
+            let board_size = match input.trim() {
+                "9" => 9,
+                "11" => 11,
+                "13" => 13,
+                _ => 7,
+            };
+            GameState::new_sized(board_size, RuleSet::default(), &engine.z_table)
+        };
+        play_game(&mut engine, limits, time_control, mode, Player::White, game, false, "");
     }
 }
@@ -1,6 +1,10 @@
 //! MCTS algorithm.
 
-use std::io::Write;
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 use rand::prelude::*;
 use rayon::prelude::*;
 
@@ -9,7 +13,9 @@ use crate::transposition::TT;
 use crate::transposition::MAX_ITER;
 use crate::transposition::WINS_BITS;
 use crate::transposition::CollisionType;
-use crate::hnefatafl::GameState;
+use crate::hnefatafl::{GameState, Undo};
+use crate::moves::{Move, MoveList};
+use crate::outcome::Player;
 
 #[derive(Clone, Copy, Debug)]
 pub enum SimulationType {
@@ -27,6 +33,90 @@ impl SimulationType {
             SimulationType::ParallelLight(n) | SimulationType::ParallelHeavy(n) => n,
         }
     }
+
+    /// The same playouts, one at a time.
+    #[inline]
+    pub fn serial(&self) -> Self {
+        match *self {
+            SimulationType::Light | SimulationType::ParallelLight(_) => SimulationType::Light,
+            SimulationType::Heavy | SimulationType::ParallelHeavy(_) => SimulationType::Heavy,
+        }
+    }
+}
+
+/// When the search of a move stops: after a thinking time, a number of playouts or a number of iterations,
+/// whichever comes first. Built with one limit; the others are added with the with_* methods.
+/// A target time (see clock::TimeManager) also stops the search early once its best move is stable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SearchLimits {
+    time: Option<Duration>,
+    /// Past this time, the search stops as soon as its best move has not changed for a while.
+    target_time: Option<Duration>,
+    /// Playouts, counting every playout of a parallel batch.
+    playouts: Option<usize>,
+    /// Iterations of the search loop (one batch of playouts each).
+    iterations: Option<u32>,
+}
+
+impl SearchLimits {
+    pub fn time(time: Duration) -> Self {
+        Self { time: Some(time), target_time: None, playouts: None, iterations: None }
+    }
+
+    pub fn playouts(playouts: usize) -> Self {
+        Self { time: None, target_time: None, playouts: Some(playouts), iterations: None }
+    }
+
+    pub fn iterations(iterations: u32) -> Self {
+        Self { time: None, target_time: None, playouts: None, iterations: Some(iterations) }
+    }
+
+    /// No limit: the search runs until it is stopped (see MCTS::ponder_while).
+    pub fn unlimited() -> Self {
+        Self { time: None, target_time: None, playouts: None, iterations: None }
+    }
+
+    pub fn with_time(self, time: Duration) -> Self {
+        Self { time: Some(time), ..self }
+    }
+
+    pub fn with_target_time(self, target_time: Duration) -> Self {
+        Self { target_time: Some(target_time), ..self }
+    }
+
+    pub fn with_playouts(self, playouts: usize) -> Self {
+        Self { playouts: Some(playouts), ..self }
+    }
+
+    pub fn with_iterations(self, iterations: u32) -> Self {
+        Self { iterations: Some(iterations), ..self }
+    }
+
+    /// Upper bound on the playouts still to come before a limit is reached (not counting the target time).
+    /// The time left is converted at the speed of the search so far.
+    fn remaining_playouts(&self, start: Instant, iterations: u32, playouts: usize, batch_size: usize) -> usize {
+        let mut remaining = usize::MAX;
+        if let Some(max) = self.iterations {
+            remaining = remaining.min(max.saturating_sub(iterations) as usize * batch_size);
+        }
+        if let Some(max) = self.playouts {
+            remaining = remaining.min(max.saturating_sub(playouts));
+        }
+        if let Some(max) = self.time {
+            let elapsed = start.elapsed();
+            let rate = playouts as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+            remaining = remaining.min((rate * max.saturating_sub(elapsed).as_secs_f64()).ceil() as usize);
+        }
+        remaining
+    }
+
+    /// True once any of the limits is reached.
+    #[inline]
+    fn reached(&self, start: Instant, iterations: u32, playouts: usize) -> bool {
+        self.iterations.is_some_and(|max| iterations >= max)
+        || self.playouts.is_some_and(|max| playouts >= max)
+        || self.time.is_some_and(|max| start.elapsed() >= max)
+    }
 }
 
 /// Negamax values.
@@ -36,15 +126,28 @@ const DRAW: isize = 0;
 /// Threshold to consider a node "Solved" in the TT.
 const SOLVED_THRESHOLD: usize = 1 << (WINS_BITS - 2);
 
+/// Result of a solved node, for the player to move in it: WIN, LOSS or DRAW (see mark_terminal).
+/// None if the node is not solved.
+#[inline]
+fn solved_score(visits: usize, wins: isize) -> Option<isize> {
+    (visits >= SOLVED_THRESHOLD).then_some(wins.signum())
+}
+
+/// Iterations between two checks of the root children (smart stop, search stability and progress).
+const ROOT_CHECK_INTERVAL: u32 = 64;
+/// Least time between two progress reports.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+/// Past the target time, the search stops once its best move has not changed for this fraction of the target.
+const STABILITY_FRACTION: u32 = 4;
+
 /// Maximum number of generations (to prevent data corruption) according to current bit layout.
 const MAX_GEN: u32 = 1 << 15; // = 2^GEN_BITS
 
-/// Maximum number of moves (estimated). Used to allocate the vector of legal moves efficiently.
-pub(crate) const MAX_MOVES: usize = 128;
+/// Expected length of a playout (estimated). Used to allocate the undo stacks of the playouts.
+const PLAYOUT_CAPACITY: usize = 128;
 
 pub struct MCTS {
     // Configuration.
-    iterations_per_move: u32, // == generation_range
     ucb_const: f64,
     
     // Used to age out old TT entries.
@@ -62,18 +165,50 @@ pub struct MCTS {
     overwritten_entries_out: usize,
 
     pub sim_type: SimulationType,
+
+    /// Undo stack of the serial playouts, reused from one playout to the next.
+    playout_undos: Vec<Undo>,
+    /// State and undo stack of each parallel playout, reused from one batch to the next.
+    parallel_playouts: Vec<(GameState, Vec<Undo>)>,
+    /// Key the TT with GameState::canonical_hash, so that rotated and mirrored positions share statistics.
+    /// Symmetric moves of the root are then searched and reported as one.
+    pub canonical_hash: bool,
+
+    /// Stop the search as soon as its choice is settled: a root move is a proven win,
+    /// or the most visited one can no longer be overtaken within the limits.
+    pub smart_stop: bool,
+
+    /// Search on the opponent's time (see ponder_while).
+    pub ponder: bool,
+    /// Set to interrupt the search in progress (see stop_flag).
+    stop: Arc<AtomicBool>,
+    /// Called with the progress of the search, every PROGRESS_INTERVAL.
+    pub on_progress: Option<Box<dyn FnMut(SearchProgress) + Send + Sync>>,
 }
 
-impl MCTS {
-    pub fn new(seed: u64, iterations_per_move: u32, sim_type: SimulationType) -> Self {
-        // To prevent overflow check: 2^VISITS_BITS > 2^GEN_BITS * iterations_per_move * batch_size
-        let batch_size = sim_type.batch_size() as u32;
-        if iterations_per_move * batch_size >= MAX_ITER {
-            panic!("Number of iteration passed might cause an overflow.");
-        }
+/// Progress of a search, reported to MCTS::on_progress.
+#[derive(Clone, Copy, Debug)]
+pub struct SearchProgress {
+    pub playouts: usize,
+    pub elapsed: Duration,
+    /// Most visited root move so far.
+    pub best: Option<Move>,
+    pub best_visits: usize,
+}
 
+/// What the search knows so far about the moves of the root.
+struct RootChildren {
+    /// Most visited move that is not a proven loss.
+    best: Option<Move>,
+    best_visits: usize,
+    /// Visits of the runner-up.
+    second_visits: usize,
+    proven_win: bool,
+}
+
+impl MCTS {
+    pub fn new(seed: u64, sim_type: SimulationType) -> Self {
         Self {
-            iterations_per_move,
             ucb_const: 1.414,
             generation: 0,
             generation_range: 1,
@@ -84,6 +219,43 @@ impl MCTS {
             overwritten_entries_in: 0,
             overwritten_entries_out: 0,
             sim_type,
+            playout_undos: Vec::with_capacity(PLAYOUT_CAPACITY),
+            parallel_playouts: Vec::new(),
+            canonical_hash: false,
+            smart_stop: true,
+            ponder: false,
+            stop: Arc::new(AtomicBool::new(false)),
+            on_progress: None,
+        }
+    }
+
+    /// Flag that interrupts the search in progress when set, e.g. from another thread.
+    /// The search then returns the best move found so far.
+    /// It is not cleared by the search: whoever sets it clears it before the next one.
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.stop)
+    }
+
+    /// Key of the state in the TT.
+    #[inline]
+    fn key(&self, state: &GameState) -> u64 {
+        if self.canonical_hash { state.canonical_hash(&self.z_table) } else { state.hash }
+    }
+
+    /// What child_key needs of the state, computed once for all its moves:
+    /// its symmetric keys with canonical_hash, nothing otherwise.
+    #[inline]
+    fn parent_keys(&self, state: &GameState) -> Option<[u64; 8]> {
+        self.canonical_hash.then(|| state.symmetric_keys(&self.z_table))
+    }
+
+    /// Key of the child reached by the move, without applying it.
+    /// parent holds the parent_keys of state.
+    #[inline]
+    fn child_key(&self, state: &GameState, parent: &Option<[u64; 8]>, m: &Move) -> u64 {
+        match parent {
+            Some(keys) => state.canonical_next_hash(m, keys, &self.z_table),
+            None => state.next_hash(m, &self.z_table),
         }
     }
 
@@ -116,7 +288,7 @@ impl MCTS {
 
     /// Mark a node as terminal (SOLVED) in the Transposition Table.
     /// This prevents re-searching a known Win/Loss/Draw.
-    /// score is usually 1, 0, or -1.
+    /// score is WIN, LOSS or DRAW, for the player to move in the node (a solved draw keeps no wins).
     fn mark_terminal(&mut self, hash: u64, score: isize) {
         let bucket = self.transpositions.get_bucket(hash);
 
@@ -154,18 +326,48 @@ impl MCTS {
 //     MCTS Algorithm
 // ======================
 impl MCTS {
-    /// Apply engine move to state.
-    pub fn computer_move<W: Write>(&mut self, state: &mut GameState, writer: &mut W) {
-        let m = self.get_move(state, writer);
-        state.move_piece(&m, &self.z_table, true, writer);
+    /// Apply engine move to state, searched within the limits.
+    /// Returns the undo record of the move.
+    pub fn computer_move<W: Write>(&mut self, state: &mut GameState, limits: &SearchLimits, writer: &mut W) -> Undo {
+        let m = self.get_move(state, limits, writer);
+        writeln!(writer, "Engine plays {}", m).expect("could not write to output");
+        state.make_move(&m, &self.z_table, true, writer)
+    }
+
+    /// Ponder: search state, where the opponent is to move, on a background thread while opponent runs
+    /// (e.g. waits for the opponent's move), then stop the search and return what opponent returned.
+    /// The statistics stay in the TT, so the search of the engine's next move starts from a warm tree.
+    /// Without the ponder option, opponent just runs.
+    /// The playouts of the pondering search run one at a time on its thread, so that the thread pool
+    /// stays free for the opponent (e.g. another engine searching its move).
+    pub fn ponder_while<T>(&mut self, state: GameState, opponent: impl FnOnce(&Zobrist) -> T) -> T {
+        if !self.ponder || state.check_game_over(&self.z_table).is_some() {
+            return opponent(&self.z_table);
+        }
+        // The search thread borrows the engine: the opponent gets its own copy of the table.
+        let z_table = self.z_table.clone();
+        let stop = Arc::clone(&self.stop);
+        stop.store(false, Ordering::Relaxed);
+        let sim_type = self.sim_type;
+        self.sim_type = sim_type.serial();
+        let result = thread::scope(|scope| {
+            let search = scope.spawn(|| self.start_search(&state, &SearchLimits::unlimited(), &mut io::sink()));
+            let result = opponent(&z_table);
+            stop.store(true, Ordering::Relaxed);
+            search.join().expect("pondering thread panicked");
+            stop.store(false, Ordering::Relaxed);
+            result
+        });
+        self.sim_type = sim_type;
+        result
     }
 
     /// Get best move according to MCTS.
-    fn get_move<W: Write>(&mut self, root: &GameState, writer: &mut W) -> [usize; 4] {
+    pub fn get_move<W: Write>(&mut self, root: &GameState, limits: &SearchLimits, writer: &mut W) -> Move {
         // Heuristics.
         #[allow(clippy::collapsible_else_if)]
-        if root.player == 'W' {
-            // 1.
+        if root.player == Player::White {
+            // 1. (Escape to a corner, or to any edge square with edge escape.)
             if let (true, Some(winning_move)) = root.heuristic_king_to_corner() {
                  return winning_move;
             }
@@ -177,64 +379,63 @@ impl MCTS {
             if let (true, Some(winning_move)) = root.heuristic_capture_king() {
                 return winning_move;
             }
+            if let (true, Some(winning_move)) = root.heuristic_surround() {
+                return winning_move;
+            }
         }
 
         // Search game tree.
-        self.start_search(root, writer);
+        self.start_search(root, limits, writer);
 
         // === CHOOSE BEST MOVE: the most visited child, considering solved childs ===
-        let mut moves = Vec::with_capacity(MAX_MOVES);
-        root.get_legal_moves(&mut moves, true);
+        let mut moves = MoveList::new();
+        root.get_legal_moves(&mut moves, true, &self.z_table);
         
         let mut moves_not_cached = 0;
 
-        let mut best_move: Option<[usize; 4]> = None;
+        let mut best_move: Option<Move> = None;
         let mut best_metric = -1.0; // We will use a mixed metric
         let mut best_wins = 0;
-        let mut forced_loss_move: Option<[usize; 4]> = None; // Fallback if everything is lost
+        let mut forced_loss_move: Option<Move> = None; // Fallback if everything is lost
         
         let mut proven_losses = 0;
 
         // Consider only moves that do NOT result in a loss for current player.
+        let mut seen_children = Vec::new();
+        let parent = self.parent_keys(root);
         for m in &moves {
-            let child_hash = root.next_hash(m, &self.z_table);
+            let child_hash = self.child_key(root, &parent, m);
+            // Symmetric moves lead to the same entry: only the first one is considered.
+            if self.canonical_hash {
+                if seen_children.contains(&child_hash) { continue; }
+                seen_children.push(child_hash);
+            }
             let child_bucket = self.transpositions.get_bucket(child_hash);
 
             let mut visits = 0;
             let mut raw_wins = 0;
-            let mut score = 0.5;
-            let mut is_solved = false;
 
             if let Some(entry) = child_bucket.get_entry(child_hash) {
                 visits = entry.get_n_visits();
                 raw_wins = entry.get_n_wins();
-
-                // Check if solved.
-                if visits >= SOLVED_THRESHOLD {
-                    is_solved = true;
-                    // Normalize score: 1.0 (Opponent Win), 0.0 (Opponent Loss), -1.0 (Draw)
-                    // Note: raw_wins for SOLVED is scaled by threshold.
-                    if raw_wins > 0 { score = 1.0; }      // Opponent Wins (BAD for us)
-                    else if raw_wins == 0 { score = 0.0; } // Opponent Loses (GOOD for us)
-                    else { score = -0.5; }                 // Draw
-                }
             } else {
                 moves_not_cached += 1;
             }
 
-            // CHOICE: 3 cases.
-
-            if is_solved {
-                if score == 0.0 {
+            // CHOICE: 3 cases. A solved child holds the result of the opponent.
+            match solved_score(visits, raw_wins) {
+                Some(LOSS) => {
                     // Case 1: proven win (opponent loses).
                     writeln!(writer, "Found PROVEN WIN move!").ok();
                     return *m;
-                } else if score == 1.0 {
+                }
+                Some(WIN) => {
                     // Case 2: proven loss (opponent wins).
                     proven_losses += 1;
                     forced_loss_move = Some(*m); // Keep one as a fallback.
                     continue;
                 }
+                _ => {}
             }
 
             // Case 3: standard choice (most visited child).
@@ -246,6 +447,9 @@ impl MCTS {
         }
         
         writeln!(writer, "Number of child moves not cached: {}", moves_not_cached).expect("could not write to output");
+        if self.canonical_hash {
+            writeln!(writer, "Symmetric moves merged: {}", moves.len() - seen_children.len()).expect("could not write to output");
+        }
         writeln!(writer, "Proven Losses avoided: {}", proven_losses).ok();
 
         // Return Best Move.
@@ -270,16 +474,17 @@ impl MCTS {
         *moves.choose(&mut rng).unwrap()
     }
 
-    fn start_search<W: Write>(&mut self, root: &GameState, writer: &mut W) {
+    fn start_search<W: Write>(&mut self, root: &GameState, limits: &SearchLimits, writer: &mut W) {
         self.increase_generation();
+        let root_hash = self.key(root);
 
         // Retrieve stats for root.
         // Root cannot have 0 visits because the first UCB value would be NaN.
         let mut root_visits = 1usize;
         let mut root_wins = 0isize;
         {
-            let bucket = self.transpositions.get_bucket(root.hash);
-            if let Some(root_entry) = bucket.get_entry(root.hash) {
+            let bucket = self.transpositions.get_bucket(root_hash);
+            if let Some(root_entry) = bucket.get_entry(root_hash) {
                 root_visits = root_entry.get_n_visits(); // Read value from cache.
                 root_wins = root_entry.get_n_wins();
             }
@@ -287,11 +492,60 @@ impl MCTS {
         if root_visits < 1 { root_visits = 1; }
 
         // SEARCH GAME TREE: SELECTION
+        // The search plays and takes back the moves on a single copy of the root.
+        let mut state = root.clone();
         let batch_size = self.sim_type.batch_size();
-        for _ in 1..self.iterations_per_move {
+        // Legal moves of the node being selected, shared by all the levels of the recursion.
+        let mut moves = MoveList::new();
+        let start = Instant::now();
+        let mut iterations = 1;
+        let mut playouts = 0;
+        // Most visited root move, and since when it is.
+        let mut best_child = None;
+        let mut best_since = start;
+        let mut last_progress = start;
+        // To prevent overflow: 2^VISITS_BITS > 2^GEN_BITS * playouts per search.
+        while !limits.reached(start, iterations, playouts) && !self.stop.load(Ordering::Relaxed) && playouts + batch_size < MAX_ITER as usize {
             // Selection and Backpropagation to the root.
-            root_wins += self.selection(root, root_visits, writer); // Increment value.
+            root_wins += self.selection(&mut state, root_visits, &mut moves, writer); // Increment value.
             root_visits += batch_size;
+            iterations += 1;
+            playouts += batch_size;
+
+            if iterations % ROOT_CHECK_INTERVAL != 0 || !(self.smart_stop || limits.target_time.is_some() || self.on_progress.is_some()) {
+                continue;
+            }
+            let children = self.root_children(root);
+
+            if let Some(on_progress) = self.on_progress.as_mut().filter(|_| last_progress.elapsed() >= PROGRESS_INTERVAL) {
+                last_progress = Instant::now();
+                on_progress(SearchProgress { playouts, elapsed: start.elapsed(), best: children.best, best_visits: children.best_visits });
+            }
+
+            // Smart stop: the choice of get_move can no longer change.
+            if self.smart_stop {
+                if children.proven_win {
+                    writeln!(writer, "Stopped early: proven win").expect("could not write to output");
+                    break;
+                }
+                let remaining = limits.remaining_playouts(start, iterations, playouts, batch_size)
+                    .min(MAX_ITER as usize - playouts);
+                if children.best_visits > children.second_visits + remaining {
+                    writeln!(writer, "Stopped early: the best move cannot be overtaken").expect("could not write to output");
+                    break;
+                }
+            }
+
+            // Search stability: past the target time, stop if the best move no longer changes.
+            if let Some(target) = limits.target_time {
+                if children.best != best_child {
+                    best_child = children.best;
+                    best_since = Instant::now();
+                }
+                if start.elapsed() >= target && best_since.elapsed() >= target / STABILITY_FRACTION {
+                    break;
+                }
+            }
         }
 
         // BACKPROPAGATION to root.
@@ -300,15 +554,15 @@ impl MCTS {
         let mut is_new_write = false;
         {
             // Add.
-            let bucket = self.transpositions.get_bucket(root.hash);
-            match bucket.add_entry(root.hash, self.generation, self.generation_bound) {
+            let bucket = self.transpositions.get_bucket(root_hash);
+            match bucket.add_entry(root_hash, self.generation, self.generation_bound) {
                 Some(CollisionType::OverwrittenIN) => { increase_collision_in = true; }
                 Some(CollisionType::OverwrittenOUT) => { increase_collision_out = true; }
                 Some(CollisionType::EmptyEntry) => { is_new_write = true; }
                 _ => {}
             }
             // Write values.
-            if let Some(root_entry) = bucket.get_entry(root.hash) {
+            if let Some(root_entry) = bucket.get_entry(root_hash) {
                 root_entry.set_n_visits(root_visits); // Update value.
                 root_entry.set_n_wins(root_wins);
             } else {
@@ -324,32 +578,69 @@ impl MCTS {
         writeln!(writer, "Number of bad collisions {}", self.overwritten_entries_in).expect("could not write to output");
         writeln!(writer, "Number of good collisions {}\n", self.overwritten_entries_out).expect("could not write to output");
 
+        writeln!(writer, "Searched {} playouts in {:.2}s", playouts, start.elapsed().as_secs_f64()).expect("could not write to output");
         writeln!(writer, "parent wins: {}", root_wins).expect("could not write to output");
         writeln!(writer, "parent visits: {}", root_visits).expect("could not write to output");
     }
 
+    /// Visits of the root moves in the TT so far, judged as get_move would:
+    /// proven losses are left out, and symmetric moves count once.
+    fn root_children(&mut self, root: &GameState) -> RootChildren {
+        let mut moves = MoveList::new();
+        root.get_legal_moves(&mut moves, true, &self.z_table);
+        let mut children = RootChildren { best: None, best_visits: 0, second_visits: 0, proven_win: false };
+        let mut seen_children = Vec::new();
+        let parent = self.parent_keys(root);
+        for m in &moves {
+            let child_hash = self.child_key(root, &parent, m);
+            if self.canonical_hash {
+                if seen_children.contains(&child_hash) { continue; }
+                seen_children.push(child_hash);
+            }
+            let Some(entry) = self.transpositions.get_bucket(child_hash).get_entry(child_hash) else { continue };
+            let visits = entry.get_n_visits();
+            match solved_score(visits, entry.get_n_wins()) {
+                // Opponent loses: proven win.
+                Some(LOSS) => children.proven_win = true,
+                // Opponent wins: proven loss, never chosen.
+                Some(WIN) => continue,
+                _ => {}
+            }
+            if visits > children.best_visits {
+                children.second_visits = children.best_visits;
+                children.best_visits = visits;
+                children.best = Some(*m);
+            } else if visits > children.second_visits {
+                children.second_visits = visits;
+            }
+        }
+        children
+    }
+
     // ========================
     //        SELECTION        
     // ========================
 
     /// Returns the result with the perspective of state.player
-    fn selection<W: Write>(&mut self, state: &GameState, node_visits: usize, writer: &mut W) -> isize {
+    /// The selected move is played on state and taken back before returning.
+    /// moves is a buffer for the legal moves: the move is chosen before going down a level,
+    /// so each level reuses it instead of keeping a list on the stack.
+    fn selection<W: Write>(&mut self, state: &mut GameState, node_visits: usize, moves: &mut MoveList, writer: &mut W) -> isize {
         let batch_size = self.sim_type.batch_size(); // <--- Get batch size
         let scaled_win = WIN * (batch_size as isize);
         let scaled_loss = LOSS * (batch_size as isize);
         let scaled_draw = DRAW * (batch_size as isize);
+        let state_hash = self.key(state);
         
         // === CHECK IF STATE IS ALREADY SOLVED IN TT ===
         // If we found this state in the TT with high visit count,
         // it means we already determined it is terminal in a previous path/search.
         {
-            let bucket = self.transpositions.get_bucket(state.hash);
-            if let Some(entry) = bucket.get_entry(state.hash)
-            && entry.get_n_visits() >= SOLVED_THRESHOLD {
+            let bucket = self.transpositions.get_bucket(state_hash);
+            if let Some(entry) = bucket.get_entry(state_hash)
+            && let Some(score) = solved_score(entry.get_n_visits(), entry.get_n_wins()) {
                 // RETURN SCALED SCORE
-                return if entry.get_n_wins() > 0 { scaled_win }
-                        else if entry.get_n_wins() < 0 { scaled_loss }
-                        else { scaled_draw };
+                return score * (batch_size as isize);
             }
         }
         
@@ -357,10 +648,11 @@ impl MCTS {
         let mut terminal_score = None;
 
         // Game over.
-        if let Some(winner) = state.check_game_over() {
-            let score = match winner {
-                'D' => scaled_draw,
-                w if w == state.player => scaled_win,
+        // Escapes, captures, exit forts and surroundings depend only on the board, so they are marked as solved.
+        if let Some(outcome) = state.check_game_over(&self.z_table) {
+            let score = match outcome.winner {
+                None => scaled_draw,
+                Some(w) if w == state.player => scaled_win,
                 _ => scaled_loss,
             };
 
@@ -375,41 +667,44 @@ impl MCTS {
 
         // Heuristics for White.
         if state.heuristic_wins_w() {
-            terminal_score = Some(if state.player == 'W' { scaled_win } else { scaled_loss });
+            terminal_score = Some(if state.player == Player::White { scaled_win } else { scaled_loss });
         }
 
         // Heuristics for Black.
-        if state.player == 'B'
-        && state.heuristic_capture_king().0 {
+        if state.player == Player::Black
+        && (state.heuristic_capture_king().0 || state.heuristic_surround().0) {
             terminal_score = Some(scaled_win);
         }
 
         // If found a terminal state, mark it and return.
         if let Some(score) = terminal_score {
-            // Note: Mark terminal uses standard 1/-1, that's fine, it handles scaling internally via SOLVED_THRESHOLD
+            // Note: Mark terminal uses standard WIN/LOSS/DRAW, it handles scaling internally via SOLVED_THRESHOLD
             // But we must return the scaled score up the stack
-            self.mark_terminal(state.hash, if score > 0 { WIN } else { LOSS });
+            self.mark_terminal(state_hash, score.signum());
             return score;
         }
 
         // === SELECTION ===
-        let selected_move: [usize; 4];
+        let selected_move: Move;
         let selected_hash: u64;
         let is_expansion_phase;
         let mut best_move_visits = 0;
         {
             // === COMPUTE UCB ===
-            let mut moves = Vec::with_capacity(MAX_MOVES);
-            state.get_legal_moves(&mut moves, true);
+            state.get_legal_moves(moves, true, &self.z_table);
 
             let mut max_ucb_value = -1.0;
-            let mut best_move: Option<[usize; 4]> = None;
+            let mut best_move: Option<Move> = None;
             let mut best_move_hash: u64 = 0;
-            
-            let mut unvisited_moves = Vec::new();
 
-            for m in &moves {
-                let child_hash = state.next_hash(m, &self.z_table);
+            // Random unvisited move (reservoir sampling), and the number of unvisited moves seen.
+            let mut rng = rand::rng();
+            let mut unvisited_move: Option<(Move, u64)> = None;
+            let mut unvisited_count = 0;
+
+            let parent = self.parent_keys(state);
+            for m in moves.iter() {
+                let child_hash = self.child_key(state, &parent, m);
                 let child_bucket = self.transpositions.get_bucket(child_hash);
                 let mut is_visited = false;
                 let mut child_visits = 0;
@@ -440,18 +735,19 @@ impl MCTS {
                         best_move_visits = child_visits;
                     }
                 } else {
-                    // If unvisited, store it for later decision.
-                    unvisited_moves.push((*m, child_hash));
+                    // If unvisited, keep it with probability 1 / (unvisited moves so far).
+                    unvisited_count += 1;
+                    if rng.random_range(0..unvisited_count) == 0 {
+                        unvisited_move = Some((*m, child_hash));
+                    }
                 }
             }
 
             // === CHOICE ===
-            if !unvisited_moves.is_empty() {
+            if let Some((m, hash)) = unvisited_move {
                 // Pick random unvisited child.
-                let idx = rand::rng().random_range(0..unvisited_moves.len());
-                let (m, h) = unvisited_moves[idx];
                 selected_move = m;
-                selected_hash = h;
+                selected_hash = hash;
                 is_expansion_phase = true;
             
             } else if let Some(m) = best_move {
@@ -469,15 +765,14 @@ impl MCTS {
 
                 // Mark this node as SOLVED in the Transposition Table
                 // We pass the unscaled 'LOSS' (-1) because mark_terminal handles the scaling internally.
-                self.mark_terminal(state.hash, LOSS);
+                self.mark_terminal(state_hash, LOSS);
 
                 return scaled_score;
             }
         }
         
         // === EXECUTE MOVE ===
-        let mut next_state = *state;
-        next_state.move_piece(&selected_move, &self.z_table, true, writer);
+        let undo = state.make_move(&selected_move, &self.z_table, true, writer);
         let result_for_child_node: isize;
 
         let visits_added = batch_size;
@@ -503,18 +798,30 @@ impl MCTS {
 
             // === SIMULATION ===
             let sim_score = match self.sim_type {
-                SimulationType::Light => self.simulation(&next_state),
-                SimulationType::Heavy => self.simulation_hard(&next_state),
-                SimulationType::ParallelLight(batch) => self.simulation_parallel(&next_state, batch, false),
-                SimulationType::ParallelHeavy(batch) => self.simulation_parallel(&next_state, batch, true),
+                SimulationType::Light | SimulationType::Heavy => {
+                    // The undo stack is lent to the playout, then put back.
+                    let mut undos = std::mem::take(&mut self.playout_undos);
+                    let score = if matches!(self.sim_type, SimulationType::Heavy) {
+                        self.simulation_hard(state, &mut undos)
+                    } else {
+                        self.simulation(state, &mut undos)
+                    };
+                    self.playout_undos = undos;
+                    score
+                }
+                SimulationType::ParallelLight(batch) => self.simulation_parallel(state, batch, false),
+                SimulationType::ParallelHeavy(batch) => self.simulation_parallel(state, batch, true),
             };
 
             result_for_child_node = sim_score;
         } else {
             // === RECURSIVE SELECTION ===
-            result_for_child_node = self.selection(&next_state, best_move_visits, writer);
+            result_for_child_node = self.selection(state, best_move_visits, moves, writer);
         }
 
+        // === TAKE BACK MOVE ===
+        state.unmake_move(&undo);
+
         // === BACKPROPAGATION ===
         // Store in the child entry the result for the child.
         {
@@ -538,12 +845,11 @@ impl MCTS {
             let child_wins = e.get_n_wins();
 
             // Check if child is solved.
-            if child_visits >= SOLVED_THRESHOLD {
-                // Case 1: Child is a PROVEN LOSS for the opponent (score == 0).
+            if let Some(child_score) = solved_score(child_visits, child_wins) {
+                // Case 1: Child is a PROVEN LOSS for the opponent.
                 // If the opponent loses in that state, it means we WIN by making this move.
-                // We assume 0 represents LOSS based on your const definitions.
-                if child_wins < 0 {
-                    self.mark_terminal(state.hash, WIN);
+                if child_score == LOSS {
+                    self.mark_terminal(state_hash, WIN);
                     // Since we found a winning move, we return WIN immediately.
                     return scaled_win; 
                 }
@@ -566,91 +872,111 @@ impl MCTS {
     // =========================
     
     /// Returns the result with the perspective of state.player
-    fn simulation(&self, state: &GameState) -> isize {
-        let mut temp_state = *state;
-        let mut moves = Vec::with_capacity(MAX_MOVES);
+    /// The moves are played on state and taken back before returning.
+    /// undos is the (empty) undo stack of the playout, kept by the caller from one playout to the next.
+    fn simulation(&self, state: &mut GameState, undos: &mut Vec<Undo>) -> isize {
+        let player = state.player;
+        let mut moves = MoveList::new();
         let mut rng = rand::rng();
 
         let mut sink = std::io::sink();
 
         // Play random moves until the game is over.
-        loop {
+        let result = loop {
             // Check game over.
-            if let Some(winner) = temp_state.check_game_over() {
-                if winner == 'D' { return DRAW; }
-                else if winner == state.player { return WIN; }
-                else { return LOSS; }
-            }
-            // Heuristics.
-            if state.heuristic_wins_w() {
-                return if state.player == 'W' { WIN } else { LOSS };
+            if let Some(outcome) = state.check_game_over(&self.z_table) {
+                match outcome.winner {
+                    None => break DRAW,
+                    Some(w) if w == player => break WIN,
+                    _ => break LOSS,
+                }
             }
-            if state.player == 'B' 
-            && state.heuristic_capture_king().0 {
-                return WIN;
+            // Heuristics (on the starting position).
+            if undos.is_empty() {
+                if state.heuristic_wins_w() {
+                    break if player == Player::White { WIN } else { LOSS };
+                }
+                if player == Player::Black
+                && state.heuristic_capture_king().0 {
+                    break WIN;
+                }
             }
 
             // Available moves.
-            temp_state.get_legal_moves(&mut moves, true);
+            state.get_legal_moves(&mut moves, true, &self.z_table);
             if moves.is_empty() {
                 // writeln!(writer, "Error: Simulation step has no moves but game over wasn't caught.").expect("could not write to output");
                 // writeln!(writer, "Applying rule 9 anyways...\n").expect("could not write to output");
                 // Current player loses (Rule 9: If a player cannot move, he loses the game).
                 // (Combined with Rule 8: If white repeats a move, he loses.)
-                if state.player == temp_state.player { return LOSS; }
-                else { return WIN; }
+                if player == state.player { break LOSS; }
+                else { break WIN; }
             }
 
             // Random move.
             let random_move = moves.choose(&mut rng).unwrap(); // returns a reference
 
             // Apply move.
-            temp_state.move_piece(random_move, &self.z_table, true, &mut sink);
-        }
+            undos.push(state.make_move(random_move, &self.z_table, true, &mut sink));
+        };
+
+        // Take back the playout.
+        while let Some(undo) = undos.pop() { state.unmake_move(&undo); }
+        result
     }
 
-    fn simulation_hard(&self, state: &GameState) -> isize {
-        let mut temp_state = *state;
-        let mut moves = Vec::with_capacity(MAX_MOVES);
-        let mut capture_moves = Vec::with_capacity(16);
+    fn simulation_hard(&self, state: &mut GameState, undos: &mut Vec<Undo>) -> isize {
+        let player = state.player;
+        let mut moves = MoveList::new();
+        let mut capture_moves = MoveList::new();
         let mut rng = rand::rng();
 
         let mut sink = std::io::sink();
 
         // Play random moves until the game is over.
-        loop {
+        let result = loop {
             // Check game over.
-            if let Some(winner) = temp_state.check_game_over() {
-                if winner == 'D' { return DRAW; }
-                else if winner == state.player { return WIN; }
-                else { return LOSS; }
-            }
-            // Heuristics (instant wins).
-            if state.heuristic_wins_w() {
-                return if state.player == 'W' { WIN } else { LOSS };
+            if let Some(outcome) = state.check_game_over(&self.z_table) {
+                match outcome.winner {
+                    None => break DRAW,
+                    Some(w) if w == player => break WIN,
+                    _ => break LOSS,
+                }
             }
-            if state.player == 'B'
-            && state.heuristic_capture_king().0 {
-                return WIN;
+            // Heuristics (instant wins, on the starting position).
+            if undos.is_empty() {
+                if state.heuristic_wins_w() {
+                    break if player == Player::White { WIN } else { LOSS };
+                }
+                if player == Player::Black
+                && state.heuristic_capture_king().0 {
+                    break WIN;
+                }
             }
 
             // Available moves.
-            temp_state.get_legal_moves(&mut moves, true);
+            state.get_legal_moves(&mut moves, true, &self.z_table);
             if moves.is_empty() {
                 // writeln!(writer, "Error: Simulation step has no moves but game over wasn't caught.").expect("could not write to output");
                 // writeln!(writer, "Applying rule 9 anyways...\n").expect("could not write to output");
                 // Current player loses (Rule 9: If a player cannot move, he loses the game).
                 // (Combined with Rule 8: If white repeats a move, he loses.)
-                if state.player == temp_state.player { return LOSS; }
-                else { return WIN; }
+                if player == state.player { break LOSS; }
+                else { break WIN; }
             }
 
             // === HARD PLAYOUTS ===
+            // Black always closes the ring when it can (rule 7b).
+            if let (true, Some(m)) = state.heuristic_surround() {
+                undos.push(state.make_move(&m, &self.z_table, true, &mut sink));
+                continue;
+            }
+
             capture_moves.clear();
 
             // Filter for captures
             for m in &moves {
-                if temp_state.is_capture_move(m) {
+                if state.is_capture_move(m) {
                     capture_moves.push(*m);
                 }
             }
@@ -668,25 +994,169 @@ impl MCTS {
             };
 
             // Apply move.
-            temp_state.move_piece(selected_move, &self.z_table, true, &mut sink);
-        }
+            undos.push(state.make_move(selected_move, &self.z_table, true, &mut sink));
+        };
+
+        // Take back the playout.
+        while let Some(undo) = undos.pop() { state.unmake_move(&undo); }
+        result
     }
 
     /// Run multiple simulations in parallel using Rayon.
     /// Returns: (Total Score, Count of Simulations)
-    fn simulation_parallel(&self, state: &GameState, batch_size: usize, use_hard: bool) -> isize {
+    fn simulation_parallel(&mut self, state: &GameState, batch_size: usize, use_hard: bool) -> isize {
+        // The states and undo stacks of the jobs are lent to the playouts, then put back.
+        // They are allocated with the first batch: later ones only copy the state into them.
+        let mut jobs = std::mem::take(&mut self.parallel_playouts);
+        if jobs.len() < batch_size {
+            jobs.resize_with(batch_size, || (state.clone(), Vec::with_capacity(PLAYOUT_CAPACITY)));
+        }
+
         // Parallel iterator using Rayon
-        let total_score: isize = (0..batch_size)
-            .into_par_iter()
-            .map(|_| {
+        let engine = &*self;
+        let total_score: isize = jobs[..batch_size]
+            .par_iter_mut()
+            .map(|(job_state, undos)| {
+                job_state.clone_window_from(state);
                 if use_hard {
-                    self.simulation_hard(state)
+                    engine.simulation_hard(job_state, undos)
                 } else {
-                    self.simulation(state)
+                    engine.simulation(job_state, undos)
                 }
             })
             .sum();
 
+        self.parallel_playouts = jobs;
         total_score
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::RuleSet;
+
+    #[test]
+    fn search_fits_a_small_stack() {
+        // Searches run on worker threads: they must fit in much less than a default 2 MB stack.
+        let search = thread::Builder::new().stack_size(128 * 1024).spawn(|| {
+            let mut engine = MCTS::new(1, SimulationType::Light);
+            let mut state = GameState::new(RuleSet::default(), &engine.z_table);
+            for _ in 0..2 {
+                if state.check_game_over(&engine.z_table).is_some() { break; }
+                engine.computer_move(&mut state, &SearchLimits::playouts(2_000), &mut io::sink());
+            }
+        }).unwrap();
+        search.join().expect("search overflowed its stack");
+    }
+
+    /// Visits of the state in the TT.
+    fn visits(engine: &mut MCTS, state: &GameState) -> usize {
+        let key = engine.key(state);
+        engine.transpositions.get_bucket(key).get_entry(key).map_or(0, |entry| entry.get_n_visits())
+    }
+
+    #[test]
+    fn ponder_while_the_opponent_thinks() {
+        let mut engine = MCTS::new(1, SimulationType::Light);
+        let state = GameState::new(RuleSet::default(), &engine.z_table);
+
+        // Without the option, the opponent just runs.
+        assert_eq!(engine.ponder_while(state.clone(), |_| 42), 42);
+        assert_eq!(visits(&mut engine, &state), 0);
+
+        engine.ponder = true;
+        let answer = engine.ponder_while(state.clone(), |_| {
+            thread::sleep(Duration::from_millis(200));
+            42
+        });
+        assert_eq!(answer, 42);
+        assert!(visits(&mut engine, &state) > 1);
+        // The flag is cleared for the next search.
+        assert!(!engine.stop_flag().load(Ordering::Relaxed));
+
+        // The pondering search leaves the thread pool to the opponent, and gets its playouts back afterwards.
+        let mut engine = MCTS::new(1, SimulationType::ParallelLight(4));
+        engine.ponder = true;
+        let answer = engine.ponder_while(state.clone(), |_| {
+            thread::sleep(Duration::from_millis(100));
+            (0..1000).into_par_iter().sum::<usize>()
+        });
+        assert_eq!(answer, 499_500);
+        assert!(visits(&mut engine, &state) > 1);
+        assert!(matches!(engine.sim_type, SimulationType::ParallelLight(4)));
+    }
+
+    #[test]
+    fn search_limits() {
+        let start = Instant::now();
+        let limits = SearchLimits::playouts(100).with_iterations(10);
+        assert!(!limits.reached(start, 5, 99));
+        assert!(limits.reached(start, 5, 100));
+        assert!(limits.reached(start, 10, 0));
+        // Iterations count batches of playouts.
+        assert_eq!(limits.remaining_playouts(start, 4, 0, 8), 48);
+        assert_eq!(limits.remaining_playouts(start, 4, 90, 8), 10);
+
+        assert!(SearchLimits::time(Duration::ZERO).reached(start, 1, 0));
+        assert!(!SearchLimits::unlimited().reached(start, u32::MAX, usize::MAX));
+        assert_eq!(SearchLimits::unlimited().remaining_playouts(start, 1, 0, 1), usize::MAX);
+    }
+
+    #[test]
+    fn search_stops_at_the_playout_limit() {
+        let mut engine = MCTS::new(1, SimulationType::ParallelLight(4));
+        engine.smart_stop = false;
+        let root = GameState::new(RuleSet::default(), &engine.z_table);
+        let mut out = Vec::new();
+        engine.start_search(&root, &SearchLimits::playouts(50), &mut out);
+        // Whole batches: the limit is reached after 13 batches of 4.
+        assert!(String::from_utf8(out).unwrap().contains("Searched 52 playouts"));
+    }
+
+    #[test]
+    fn solved_children_at_the_root() {
+        let mut engine = MCTS::new(1, SimulationType::Light);
+        let root = GameState::new(RuleSet::default(), &engine.z_table);
+        let mut moves = MoveList::new();
+        root.get_legal_moves(&mut moves, true, &engine.z_table);
+        let (winning, losing, drawn) = (moves[0], moves[1], moves[2]);
+
+        // The children hold the results of the opponent.
+        engine.mark_terminal(engine.child_key(&root, &engine.parent_keys(&root), &losing), WIN);
+        engine.mark_terminal(engine.child_key(&root, &engine.parent_keys(&root), &drawn), DRAW);
+        let children = engine.root_children(&root);
+        assert!(!children.proven_win);
+        assert_eq!(children.best, Some(drawn));
+
+        engine.mark_terminal(engine.child_key(&root, &engine.parent_keys(&root), &winning), LOSS);
+        assert!(engine.root_children(&root).proven_win);
+        assert_eq!(engine.get_move(&root, &SearchLimits::playouts(100), &mut io::sink()), winning);
+    }
+
+    #[test]
+    fn smart_stop_on_a_proven_win() {
+        let mut engine = MCTS::new(1, SimulationType::Light);
+        let root = GameState::new(RuleSet::default(), &engine.z_table);
+        let mut moves = MoveList::new();
+        root.get_legal_moves(&mut moves, true, &engine.z_table);
+        engine.mark_terminal(engine.child_key(&root, &engine.parent_keys(&root), &moves[0]), LOSS);
+
+        let mut out = Vec::new();
+        engine.start_search(&root, &SearchLimits::playouts(100_000), &mut out);
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Stopped early: proven win"));
+        assert!(out.contains(&format!("Searched {} playouts", ROOT_CHECK_INTERVAL - 1)));
+    }
+
+    #[test]
+    fn canonical_hash_merges_symmetric_moves() {
+        let mut engine = MCTS::new(1, SimulationType::Light);
+        engine.canonical_hash = true;
+        let root = GameState::new(RuleSet::default(), &engine.z_table);
+        let mut out = Vec::new();
+        engine.get_move(&root, &SearchLimits::playouts(200), &mut out);
+        // The 40 moves of the symmetric start fall into 5 classes.
+        assert!(String::from_utf8(out).unwrap().contains("Symmetric moves merged: 35"));
+    }
+}
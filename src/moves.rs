@@ -0,0 +1,196 @@
+//! Moves and their notation.
+//! A move is written in tafl notation: <from>-<to>, e.g. d1-d3.
+//! Files are letters from the left (a = column 0), ranks are numbers from the bottom
+//! (1 = the last row, so a1 is the bottom left corner), as printed by GameState::display.
+//! Moves are stored by file and rank, so the notation does not depend on the board size;
+//! the rows and columns of the board do (see Move::new and Move::coords).
+
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+use crate::bitboard::{MAX_BOARD_SIZE, MAX_SQUARES};
+
+/// A piece moving along a row or a column.
+/// Files and ranks (from 0) are stored as bytes to keep move lists small.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Move {
+    start_file: u8,
+    start_rank: u8,
+    end_file: u8,
+    end_rank: u8,
+}
+
+impl Move {
+    /// Placeholder used to fill the unused slots of a MoveList.
+    const NULL: Move = Move { start_file: 0, start_rank: 0, end_file: 0, end_rank: 0 };
+
+    /// Move between two squares given by row (from the top) and column, on a board of the given size.
+    #[inline(always)]
+    pub fn new(start_row: usize, start_col: usize, end_row: usize, end_col: usize, size: usize) -> Self {
+        Self {
+            start_file: start_col as u8,
+            start_rank: (size - 1 - start_row) as u8,
+            end_file: end_col as u8,
+            end_rank: (size - 1 - end_row) as u8,
+        }
+    }
+
+    /// (start_row, start_col, end_row, end_col) on a board of the given size, which the move must fit (see fits).
+    #[inline(always)]
+    pub fn coords(&self, size: usize) -> (usize, usize, usize, usize) {
+        let last = size - 1;
+        (last - self.start_rank as usize, self.start_file as usize, last - self.end_rank as usize, self.end_file as usize)
+    }
+
+    /// True if both squares are on a board of the given size.
+    pub fn fits(&self, size: usize) -> bool {
+        [self.start_file, self.start_rank, self.end_file, self.end_rank].iter().all(|&x| (x as usize) < size)
+    }
+
+    /// Parse a move in tafl notation (e.g. d1-d3) on a board of the given size.
+    /// Only the syntax and the board limits are checked, not whether the move is legal.
+    pub fn parse(s: &str, size: usize) -> Result<Move, String> {
+        let m: Move = s.parse()?;
+        if !m.fits(size) {
+            return Err(format!("{} is out of the board", m));
+        }
+        Ok(m)
+    }
+}
+
+/// Square in tafl notation, e.g. d1.
+fn write_square(f: &mut fmt::Formatter, file: u8, rank: u8) -> fmt::Result {
+    write!(f, "{}{}", (b'a' + file) as char, rank + 1)
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_square(f, self.start_file, self.start_rank)?;
+        write!(f, "-")?;
+        write_square(f, self.end_file, self.end_rank)
+    }
+}
+
+impl FromStr for Move {
+    type Err = String;
+
+    /// Parse a move in tafl notation (e.g. d1-d3), on any board up to MAX_BOARD_SIZE.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s.trim().split_once('-').ok_or("expected a move like d1-d3")?;
+        let (start_file, start_rank) = parse_square(from.trim())?;
+        let (end_file, end_rank) = parse_square(to.trim())?;
+        Ok(Move { start_file, start_rank, end_file, end_rank })
+    }
+}
+
+/// Parse a square (e.g. d1) into (file, rank), counted from 0.
+fn parse_square(s: &str) -> Result<(u8, u8), String> {
+    let mut chars = s.chars();
+    let file = chars.next().ok_or("empty square")?.to_ascii_lowercase();
+    if !file.is_ascii_lowercase() {
+        return Err(format!("invalid file in {}", s));
+    }
+    let file = (file as u8 - b'a') as usize;
+    let rank: usize = chars.as_str().parse().map_err(|_| format!("invalid rank in {}", s))?;
+    if file >= MAX_BOARD_SIZE || rank == 0 || rank > MAX_BOARD_SIZE {
+        return Err(format!("square {} is out of the board", s));
+    }
+    Ok((file as u8, (rank - 1) as u8))
+}
+
+// ========================
+//        MOVE LIST
+// ========================
+
+/// Upper bound on the number of legal moves in any position:
+/// an empty square can be reached from at most four directions.
+pub const MAX_LEGAL_MOVES: usize = 4 * MAX_SQUARES;
+
+/// Fixed capacity list of moves, stored on the stack.
+/// Move generation fills it in place, so the hot loops of the search never allocate.
+/// Dereferences to a slice of the moves in the list.
+#[derive(Clone)]
+pub struct MoveList {
+    moves: [Move; MAX_LEGAL_MOVES],
+    len: usize,
+}
+
+impl MoveList {
+    #[inline]
+    pub fn new() -> Self {
+        Self { moves: [Move::NULL; MAX_LEGAL_MOVES], len: 0 }
+    }
+
+    #[inline(always)]
+    pub fn push(&mut self, m: Move) {
+        self.moves[self.len] = m;
+        self.len += 1;
+    }
+
+    #[inline(always)]
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+impl Default for MoveList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for MoveList {
+    type Target = [Move];
+
+    #[inline(always)]
+    fn deref(&self) -> &[Move] {
+        &self.moves[..self.len]
+    }
+}
+
+impl<'a> IntoIterator for &'a MoveList {
+    type Item = &'a Move;
+    type IntoIter = std::slice::Iter<'a, Move>;
+
+    #[inline(always)]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranks_from_the_bottom() {
+        // a1 is the bottom left corner.
+        assert_eq!(Move::parse("a1-a3", 7), Ok(Move::new(6, 0, 4, 0, 7)));
+        assert_eq!(Move::parse(" K11-k9 ", 11), Ok(Move::new(0, 10, 2, 10, 11)));
+        assert_eq!(Move::new(6, 0, 4, 0, 7).to_string(), "a1-a3");
+        assert_eq!(Move::new(0, 3, 0, 5, 13).to_string(), "d13-f13");
+        assert_eq!(Move::new(0, 3, 0, 5, 13).coords(13), (0, 3, 0, 5));
+    }
+
+    #[test]
+    fn notation_round_trip() {
+        for size in [7, 9, 11, 13] {
+            for (r, c) in [(0, 0), (size - 1, size - 1), (size / 2, 1)] {
+                let m = Move::new(r, c, size - 1 - r, c, size);
+                assert_eq!(m.to_string().parse(), Ok(m));
+                assert_eq!(Move::parse(&m.to_string(), size), Ok(m));
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_moves() {
+        for s in ["", "a1", "a1-", "a0-a2", "a8-a2", "h1-h2", "11-a2", "a1-ax"] {
+            assert!(Move::parse(s, 7).is_err(), "{} parsed", s);
+        }
+        // The notation alone only limits the squares to the largest board.
+        assert!("a13-m13".parse::<Move>().is_ok());
+        assert!("a14-a1".parse::<Move>().is_err());
+        assert!("n1-a1".parse::<Move>().is_err());
+    }
+}
@@ -0,0 +1,134 @@
+//! Players, pieces, and the results of moves and games.
+
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Player {
+    /// Attackers. Black moves first.
+    Black,
+    /// Defenders, including the king.
+    White,
+}
+
+impl Player {
+    #[inline(always)]
+    pub fn opponent(self) -> Player {
+        match self {
+            Player::Black => Player::White,
+            Player::White => Player::Black,
+        }
+    }
+}
+
+impl fmt::Display for Player {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Player::Black => write!(f, "Black"),
+            Player::White => write!(f, "White"),
+        }
+    }
+}
+
+/// Kinds of pieces on the board.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Piece {
+    /// Black pawn (attacker).
+    Black,
+    /// White pawn (defender).
+    White,
+    King,
+}
+
+/// A piece removed from the board, and the square (row, col) it stood on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Capture {
+    pub row: usize,
+    pub col: usize,
+    pub piece: Piece,
+}
+
+/// What a move did, as returned by GameState::move_piece.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MoveOutcome {
+    /// Every piece captured by the move (the king included), in board order.
+    pub captures: Vec<Capture>,
+    pub king_captured: bool,
+    /// The move repeated a position often enough for the repetition rule to apply.
+    pub repetition: bool,
+}
+
+/// Why a game ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EndReason {
+    /// The king reached a corner (or any edge square with edge escape).
+    KingEscaped,
+    KingCaptured,
+    /// Rule 6b.
+    ExitFort,
+    /// Rule 7b.
+    Surrounded,
+    /// Rule 8.
+    Repetition,
+    /// Rule 9: the player to move has no legal move.
+    NoLegalMoves,
+    /// Rule 10: neither side has enough pieces left to win.
+    InsufficientMaterial,
+    /// The player to move ran out of time (see GameClock).
+    TimeForfeit,
+}
+
+impl fmt::Display for EndReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            EndReason::KingEscaped => "the king escaped",
+            EndReason::KingCaptured => "the king was captured",
+            EndReason::ExitFort => "the king built an exit fort",
+            EndReason::Surrounded => "all white pieces are surrounded",
+            EndReason::Repetition => "repetition",
+            EndReason::NoLegalMoves => "no legal moves",
+            EndReason::InsufficientMaterial => "insufficient material",
+            EndReason::TimeForfeit => "time ran out",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// Result of a finished game: the winner (None for a draw) and why the game ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GameOutcome {
+    pub winner: Option<Player>,
+    pub reason: EndReason,
+}
+
+impl GameOutcome {
+    #[inline(always)]
+    pub fn win(winner: Player, reason: EndReason) -> Self {
+        Self { winner: Some(winner), reason }
+    }
+
+    #[inline(always)]
+    pub fn draw(reason: EndReason) -> Self {
+        Self { winner: None, reason }
+    }
+}
+
+impl fmt::Display for GameOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.winner {
+            Some(player) => write!(f, "{} wins! ({})", player, self.reason),
+            None => write!(f, "Draw. ({})", self.reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outcome_display() {
+        assert_eq!(Player::Black.opponent(), Player::White);
+        assert_eq!(GameOutcome::win(Player::White, EndReason::KingEscaped).to_string(), "White wins! (the king escaped)");
+        assert_eq!(GameOutcome::draw(EndReason::Repetition).to_string(), "Draw. (repetition)");
+    }
+}
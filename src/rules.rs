@@ -0,0 +1,171 @@
+//! Configurable rules.
+//! A RuleSet is passed to GameState::new and copied with the state, so every
+//! rule check (captures, game over, move filtering) reads the variant being played.
+//! A Preset bundles the rules of a named variant with its starting position (see GameState::from_preset).
+
+/// How many sides the king has to be surrounded on to be captured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KingCapture {
+    /// Four sides on the throne, three sides + throne next to it, two sides (like a pawn) elsewhere.
+    Standard,
+    /// Always two sides, like a pawn.
+    TwoSides,
+    /// Always four sides. Hostile squares count as attackers; a king on the edge cannot be captured.
+    FourSides,
+}
+
+/// What happens when a board position is repeated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RepetitionRule {
+    /// Rule 8: perpetual repetition is a loss for white.
+    WhiteLoses,
+    /// The game ends in a draw.
+    Draw,
+    /// Repetitions have no effect.
+    Allowed,
+}
+
+/// Squares the king has to reach to win.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Escape {
+    /// The four corners.
+    Corners,
+    /// Any square on the edge, as in Tablut. The corners are ordinary squares.
+    Edges,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RuleSet {
+    /// The corners are hostile to all pieces, including the king.
+    pub hostile_corners: bool,
+    /// The throne is hostile to black (whether the king is on it or not).
+    pub throne_hostile_to_black: bool,
+    /// The empty throne is hostile to white, including the king.
+    pub empty_throne_hostile_to_white: bool,
+    /// The king takes part in captures, both moving and as an anvil.
+    pub king_armed: bool,
+    pub king_capture: KingCapture,
+    pub escape: Escape,
+    pub repetition: RepetitionRule,
+    /// How many times a position has to occur for the repetition rule to apply
+    /// (2: as soon as it is repeated once, 3: threefold repetition).
+    pub repetition_count: usize,
+    /// Positions only count as repeated if the same side is to move.
+    pub repetition_side_to_move: bool,
+    /// Rule 4b: a row of pieces on the edge can be captured as a group.
+    pub shieldwall: bool,
+    /// Rule 6b: white wins if the king builds an exit fort on the edge.
+    pub exit_forts: bool,
+    /// Rule 7b: black wins by enclosing the king and all the white pieces.
+    pub surrounding: bool,
+}
+
+impl RuleSet {
+    /// Simplified Copenhagen rules (the rules the engine was written for).
+    pub fn copenhagen() -> Self {
+        Self {
+            hostile_corners: true,
+            throne_hostile_to_black: true,
+            empty_throne_hostile_to_white: true,
+            king_armed: true,
+            king_capture: KingCapture::Standard,
+            escape: Escape::Corners,
+            repetition: RepetitionRule::WhiteLoses,
+            repetition_count: 2,
+            repetition_side_to_move: false,
+            shieldwall: false,
+            exit_forts: false,
+            surrounding: false,
+        }
+    }
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        Self::copenhagen()
+    }
+}
+
+/// Named variants: a board size, a starting position and the rules they are played with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Preset {
+    /// 7x7, 8 attackers against 4 defenders. The king is captured like a pawn.
+    Brandubh,
+    /// 7x7, 16 attackers against 8 defenders. The king has to be surrounded on four sides.
+    ArdRi,
+    /// 9x9, 16 attackers against 8 defenders. The king is not armed and escapes to any edge square.
+    Tablut,
+    /// 11x11, full Copenhagen rules (shieldwalls, exit forts, surrounding, threefold repetition).
+    Copenhagen,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 4] = [Preset::Brandubh, Preset::ArdRi, Preset::Tablut, Preset::Copenhagen];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Preset::Brandubh => "brandubh",
+            Preset::ArdRi => "ard-ri",
+            Preset::Tablut => "tablut",
+            Preset::Copenhagen => "copenhagen",
+        }
+    }
+
+    /// Case insensitive lookup by name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.name().eq_ignore_ascii_case(name.trim()))
+    }
+
+    pub fn board_size(&self) -> usize {
+        match self {
+            Preset::Brandubh | Preset::ArdRi => 7,
+            Preset::Tablut => 9,
+            Preset::Copenhagen => 11,
+        }
+    }
+
+    pub fn rules(&self) -> RuleSet {
+        match self {
+            Preset::Brandubh => RuleSet {
+                king_capture: KingCapture::TwoSides,
+                repetition: RepetitionRule::Draw,
+                ..RuleSet::copenhagen()
+            },
+            Preset::ArdRi => RuleSet {
+                king_capture: KingCapture::FourSides,
+                repetition: RepetitionRule::Draw,
+                ..RuleSet::copenhagen()
+            },
+            Preset::Tablut => RuleSet {
+                hostile_corners: false,
+                king_armed: false,
+                escape: Escape::Edges,
+                repetition: RepetitionRule::Draw,
+                ..RuleSet::copenhagen()
+            },
+            Preset::Copenhagen => RuleSet {
+                king_capture: KingCapture::FourSides,
+                repetition_count: 3,
+                repetition_side_to_move: true,
+                shieldwall: true,
+                exit_forts: true,
+                surrounding: true,
+                ..RuleSet::copenhagen()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preset_names() {
+        for preset in Preset::ALL {
+            assert_eq!(Preset::from_name(preset.name()), Some(preset));
+        }
+        assert_eq!(Preset::from_name(" Ard-Ri "), Some(Preset::ArdRi));
+        assert_eq!(Preset::from_name("fetlar"), None);
+    }
+}
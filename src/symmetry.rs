@@ -0,0 +1,82 @@
+//! Symmetries of the square board: the four rotations and the four reflections.
+//! The throne, the corners and the rules are invariant under all of them, so a rotated or
+//! mirrored position plays exactly like the original (see GameState::canonical_hash).
+
+use crate::bitboard::Bitboard;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Symmetry {
+    Identity,
+    /// Quarter turn clockwise.
+    Rotate90,
+    Rotate180,
+    /// Quarter turn counterclockwise.
+    Rotate270,
+    /// Top and bottom rows swapped.
+    FlipRows,
+    /// Left and right columns swapped.
+    FlipCols,
+    /// Mirror along the diagonal from the top left to the bottom right corner (rows and columns swapped).
+    FlipDiagonal,
+    /// Mirror along the diagonal from the bottom left to the top right corner.
+    FlipAntiDiagonal,
+}
+
+impl Symmetry {
+    pub const ALL: [Symmetry; 8] = [
+        Symmetry::Identity, Symmetry::Rotate90, Symmetry::Rotate180, Symmetry::Rotate270,
+        Symmetry::FlipRows, Symmetry::FlipCols, Symmetry::FlipDiagonal, Symmetry::FlipAntiDiagonal,
+    ];
+
+    /// Image of the square (r, c) on a board of the given size.
+    #[inline(always)]
+    pub fn apply(self, r: usize, c: usize, size: usize) -> (usize, usize) {
+        let last = size - 1;
+        match self {
+            Symmetry::Identity => (r, c),
+            Symmetry::Rotate90 => (c, last - r),
+            Symmetry::Rotate180 => (last - r, last - c),
+            Symmetry::Rotate270 => (last - c, r),
+            Symmetry::FlipRows => (last - r, c),
+            Symmetry::FlipCols => (r, last - c),
+            Symmetry::FlipDiagonal => (c, r),
+            Symmetry::FlipAntiDiagonal => (last - c, last - r),
+        }
+    }
+
+    pub fn apply_board(self, board: Bitboard, size: usize) -> Bitboard {
+        let mut image = Bitboard::EMPTY;
+        for i in board.iter() {
+            let (r, c) = self.apply(i / size, i % size, size);
+            image.set(r * size + c);
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symmetries_permute_the_squares() {
+        let size = 7;
+        let mut corners = Bitboard::EMPTY;
+        for i in [0, size - 1, size * (size - 1), size * size - 1] { corners.set(i); }
+        let throne = Bitboard::from_index(size * size / 2);
+        let mut images = Vec::new();
+        for sym in Symmetry::ALL {
+            assert_eq!(sym.apply_board(corners, size), corners);
+            assert_eq!(sym.apply_board(throne, size), throne);
+            // Every square has one image.
+            let mut all = Bitboard::EMPTY;
+            for i in 0..size * size { all.set(i); }
+            assert_eq!(sym.apply_board(all, size), all);
+            images.push(sym.apply(0, 1, size));
+        }
+        // The eight images of a square off the axes are all different.
+        images.sort_unstable();
+        images.dedup();
+        assert_eq!(images.len(), 8);
+    }
+}
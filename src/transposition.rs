@@ -25,7 +25,7 @@ const GEN_BITS: u32 = 13;
 const VISITS_BITS: u32 = 37;
 pub const WINS_BITS: u32 = 38;
 /// To be sure the program doesn't get to an overflow, we force the following condition:
-/// 2^VISITS_BITS > 2^GEN_BITS * iterations_per_move
+/// 2^VISITS_BITS > 2^GEN_BITS * playouts per search (MCTS::start_search stops before MAX_ITER).
 const MAX_ITER_BITS: u32 = VISITS_BITS - GEN_BITS;
 pub const MAX_ITER: u32 = 1u32 << MAX_ITER_BITS; // used in mcts.rs
 /// Offsets.
@@ -2,16 +2,17 @@
 
 use rand::{Rng, SeedableRng};
 use rand::rngs::StdRng;
+use crate::bitboard::MAX_BOARD_SIZE;
 
 // Padded to 4 (power of 2) to ensure 32-byte alignment per cell,
 // preventing cache line splits and allowing bit-shift indexing.
 const PIECE_TYPES: usize = 4; // B, W, K, padding.
-const BOARD_SIZE: usize = 7;
 
 #[derive(Clone)]
 pub struct Zobrist {
-    // 7 * 7 * 4 * 8 bytes = 1568 bytes (Fits in L1 Cache)
-    pub table: [[[u64; PIECE_TYPES]; BOARD_SIZE]; BOARD_SIZE],
+    // Sized for the largest board, so one table serves every variant.
+    // 13 * 13 * 4 * 8 bytes = 5408 bytes (Fits in L1 Cache)
+    pub table: [[[u64; PIECE_TYPES]; MAX_BOARD_SIZE]; MAX_BOARD_SIZE],
     pub black_to_move: u64,
 }
 
@@ -19,7 +20,7 @@ impl Zobrist {
     pub fn new(seed: u64) -> Self {
         let mut rng = StdRng::seed_from_u64(seed);
 
-        let mut table = [[[0u64; PIECE_TYPES]; BOARD_SIZE]; BOARD_SIZE];
+        let mut table = [[[0u64; PIECE_TYPES]; MAX_BOARD_SIZE]; MAX_BOARD_SIZE];
         for piece in table.iter_mut().flatten().flatten() {
             *piece = rng.random::<u64>();
         }